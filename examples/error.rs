@@ -8,24 +8,13 @@ extern crate time;
 use std::io::net::ip::Ipv4Addr;
 use std::fmt::Show;
 use iron::{Iron, Chain, Request, Response,
-           Middleware, Server, Status,
-           Error, FromFn};
+           Server, Status, Error,
+           FromFn, ErrorHandler};
 use http::status;
 
-#[deriving(Clone)]
-struct ErrorHandler;
-
-impl ErrorHandler { fn new() -> ErrorHandler { ErrorHandler } }
-
-impl Middleware for ErrorHandler {
-    fn on_error(&mut self,
-                _: &mut Request,
-                res: &mut Response,
-                _: &mut Show) {
-        error!("Error when handling request.");
-        res.serve(status::InternalServerError, "Internal Server Error.");
-    }
-
+fn handle_error(_: &mut Request, res: &mut Response, _: &mut Show) {
+    error!("Error when handling request.");
+    res.serve(status::InternalServerError, "Internal Server Error.");
 }
 
 fn error(_: &mut Request, _: &mut Response) -> Status {
@@ -35,7 +24,7 @@ fn error(_: &mut Request, _: &mut Response) -> Status {
 fn main() {
     let mut server: Server = Iron::new();
 
-    server.chain.link(ErrorHandler::new());
+    server.chain.link(ErrorHandler::new(handle_error));
     server.chain.link(FromFn::new(error));
 
     // Start the server on localhost:3000
@@ -0,0 +1,220 @@
+//! Exposes the `AccessLog` middleware and the `LogContext` other
+//! `Middleware` use to add fields to its log lines.
+
+use std::fmt::Show;
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue, ErrorStatus};
+use super::logger::Logger;
+
+/// Structured fields to be logged for a request.
+///
+/// `AccessLog` stores a `LogContext` in `Request::alloy` on `enter`, and
+/// any `Middleware` after it can add fields to it:
+///
+/// ```ignore
+/// match req.alloy.find_mut::<LogContext>() {
+///     Some(context) => context.add_field("user", user.id.as_slice()),
+///     None => ()
+/// }
+/// ```
+#[deriving(Clone, Show)]
+pub struct LogContext {
+    fields: Vec<(String, String)>
+}
+
+impl LogContext {
+    /// Create a new, empty `LogContext`.
+    pub fn new() -> LogContext {
+        LogContext { fields: vec![] }
+    }
+
+    /// Add a field, which is logged after all those added before it.
+    pub fn add_field(&mut self, key: &str, value: &str) {
+        self.fields.push((key.to_string(), value.to_string()));
+    }
+
+    /// The fields added so far.
+    pub fn fields<'a>(&'a self) -> &'a [(String, String)] {
+        self.fields.as_slice()
+    }
+
+    /// Serialize the fields as a logfmt line, quoting values where needed.
+    ///
+    /// Backslashes, quotes and line breaks in quoted values are escaped,
+    /// so that a value can't end the line and forge another.
+    pub fn to_logfmt(&self) -> String {
+        let pairs: Vec<String> = self.fields.iter().map(|&(ref key, ref value)| {
+            if value.is_empty() || value.as_slice().chars().any(needs_quotes) {
+                let mut quoted = String::new();
+                for c in value.as_slice().chars() {
+                    match c {
+                        '\\' => quoted.push_str("\\\\"),
+                        '"' => quoted.push_str("\\\""),
+                        '\n' => quoted.push_str("\\n"),
+                        '\r' => quoted.push_str("\\r"),
+                        c => quoted.push_char(c)
+                    }
+                }
+                format!("{}=\"{}\"", key, quoted)
+            } else {
+                format!("{}={}", key, value)
+            }
+        }).collect();
+        pairs.connect(" ")
+    }
+}
+
+/// `Middleware` which logs one logfmt line per request in `exit`, with the
+/// method, path and status followed by every field added to the request's
+/// `LogContext`.
+///
+/// Requests which end in an `Error` are logged from `on_error` instead,
+/// with the status they will be answered with and an `error` field.
+///
+/// `AccessLog` should be linked first, so that all other `Middleware`
+/// can add fields.
+#[deriving(Clone)]
+pub struct AccessLog {
+    logger: Box<Logger + Send>
+}
+
+impl AccessLog {
+    /// Create a new `AccessLog` writing to `logger`.
+    pub fn new(logger: Box<Logger + Send>) -> AccessLog {
+        AccessLog { logger: logger }
+    }
+
+    // Log the line for `req`, answered with `status`.
+    fn log(&mut self, req: &Request, status: String, error: Option<String>) {
+        let mut line = LogContext::new();
+        match req.alloy.find::<LogContext>() {
+            Some(context) => {
+                let mut fields = context.fields().iter();
+                // Keep method and path first, then the status.
+                for &(ref key, ref value) in fields.by_ref().take(2) {
+                    line.add_field(key.as_slice(), value.as_slice());
+                }
+                line.add_field("status", status.as_slice());
+                for &(ref key, ref value) in fields {
+                    line.add_field(key.as_slice(), value.as_slice());
+                }
+            },
+            None => line.add_field("status", status.as_slice())
+        }
+        match error {
+            Some(error) => line.add_field("error", error.as_slice()),
+            None => ()
+        }
+
+        self.logger.log(line.to_logfmt().as_slice());
+    }
+}
+
+impl Middleware for AccessLog {
+    fn enter(&mut self, req: &mut Request, _: &mut Response) -> Status {
+        let mut context = LogContext::new();
+        context.add_field("method", req.method.to_string().as_slice());
+        context.add_field("path", req.url.serialize_path().unwrap_or(String::new()).as_slice());
+        req.alloy.insert::<LogContext>(context);
+        Continue
+    }
+
+    fn exit(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        let status = match res.status {
+            Some(ref status) => status.code().to_string(),
+            None => "404".to_string()
+        };
+        self.log(req, status, None);
+        Continue
+    }
+
+    fn on_error(&mut self, req: &mut Request, res: &mut Response, error: &mut Show) {
+        let status = match (&res.status, req.alloy.find::<ErrorStatus>()) {
+            (&Some(ref status), _) => status.code(),
+            (&None, Some(&ErrorStatus(ref status))) => status.code(),
+            (&None, None) => 500
+        };
+        self.log(req, status.to_string(), Some(format!("{}", error)));
+    }
+}
+
+// Whether a logfmt value has to be quoted.
+fn needs_quotes(c: char) -> bool {
+    c == ' ' || c == '=' || c == '"' || c == '\\' || c.is_control()
+}
+
+#[cfg(test)]
+mod test {
+    use http::method::Get;
+
+    use super::{AccessLog, LogContext};
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::middleware::{Status, Continue, Unwind, Error, FromFn};
+
+    fn authenticate(req: &mut Request, _: &mut Response) -> Status {
+        req.alloy.find_mut::<LogContext>().unwrap().add_field("user", "42");
+        Continue
+    }
+
+    fn cached(req: &mut Request, res: &mut Response) -> Status {
+        req.alloy.find_mut::<LogContext>().unwrap().add_field("cache", "hit");
+        res.serve(::http::status::Ok, "cached");
+        Unwind
+    }
+
+    #[test]
+    fn logs_fields_from_all_middleware() {
+        let logger = mock::CaptureLogger::new();
+        let mut chain: StackChain = Chain::new();
+        chain.link(AccessLog::new(logger.boxed()));
+        chain.link(FromFn::new(authenticate));
+        chain.link(FromFn::new(cached));
+
+        let _ = chain.dispatch(&mut mock::request(Get, "http://localhost/feed"),
+                               &mut mock::response());
+
+        assert_eq!(logger.lines(),
+                   vec!["method=GET path=/feed status=200 user=42 cache=hit".to_string()]);
+    }
+
+    fn fails(_: &mut Request, _: &mut Response) -> Status {
+        Error(box "no such feed" as Box<::std::fmt::Show>)
+    }
+
+    #[test]
+    fn logs_errored_requests() {
+        let logger = mock::CaptureLogger::new();
+        let mut chain: StackChain = Chain::new();
+        chain.link(AccessLog::new(logger.boxed()));
+        chain.link(FromFn::new(fails));
+
+        let _ = chain.dispatch(&mut mock::request(Get, "http://localhost/feed"),
+                               &mut mock::response());
+
+        assert_eq!(logger.lines(),
+                   vec!["method=GET path=/feed status=500 error=\"no such feed\"".to_string()]);
+    }
+
+    #[test]
+    fn escapes_line_breaks_and_backslashes() {
+        let mut context = LogContext::new();
+        context.add_field("agent", "x\nmethod=POST");
+        context.add_field("path", "a\\\"b");
+        assert_eq!(context.to_logfmt().as_slice(),
+                   "agent=\"x\\nmethod=POST\" path=\"a\\\\\\\"b\"");
+    }
+
+    #[test]
+    fn quotes_values() {
+        let mut context = LogContext::new();
+        context.add_field("agent", "curl 7.0");
+        context.add_field("empty", "");
+        assert_eq!(context.to_logfmt().as_slice(), "agent=\"curl 7.0\" empty=\"\"");
+    }
+}
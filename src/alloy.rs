@@ -55,7 +55,7 @@ impl Alloy {
 
     /// Remove a value from the `Alloy`.
     pub fn remove<T: 'static>(&mut self) {
-        self.remove::<T>()
+        let _ = self.map.remove::<T>();
     }
 }
 
@@ -0,0 +1,96 @@
+//! Exposes the `CachePolicy` middleware, which sets `Cache-Control`
+//! by path.
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue};
+use super::router::Pattern;
+
+/// `Middleware` which sets the `Cache-Control` header of responses
+/// in `exit`, from the first rule whose `Pattern` matches the path:
+///
+/// ```ignore
+/// server.chain.link(CachePolicy::new("no-cache")
+///                   .rule("/static/*path", "public, max-age=31536000"));
+/// ```
+///
+/// Patterns are matched like `Router` routes. Paths matching no rule
+/// get the default directive. A response which already has a
+/// `Cache-Control` header, set by its handler, is left alone.
+#[deriving(Clone)]
+pub struct CachePolicy {
+    rules: Vec<(Pattern, String)>,
+    default: String
+}
+
+impl CachePolicy {
+    /// Create a new `CachePolicy` using `default` for paths matching no rule.
+    pub fn new(default: &str) -> CachePolicy {
+        CachePolicy { rules: vec![], default: default.to_string() }
+    }
+
+    /// Use `directive` for paths matching `pattern`, unless an earlier
+    /// rule matches.
+    ///
+    /// Fails if `pattern` is invalid, see `Pattern`.
+    pub fn rule(mut self, pattern: &str, directive: &str) -> CachePolicy {
+        self.rules.push((Pattern::new(pattern), directive.to_string()));
+        self
+    }
+
+    fn directive<'a>(&'a self, path: &[String]) -> &'a str {
+        for &(ref pattern, ref directive) in self.rules.iter() {
+            if pattern.matches(path).is_some() { return directive.as_slice() }
+        }
+        self.default.as_slice()
+    }
+}
+
+impl Middleware for CachePolicy {
+    fn exit(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        if res.headers.cache_control.is_none() {
+            res.headers.cache_control = Some(self.directive(req.path.as_slice()).to_string());
+        }
+        Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::method::Get;
+
+    use super::CachePolicy;
+    use super::super::mock;
+    use super::super::middleware::Middleware;
+
+    fn cache_control(path: &str) -> Option<String> {
+        let mut policy = CachePolicy::new("no-cache")
+            .rule("/static/*path", "public, max-age=31536000");
+
+        let mut res = mock::response();
+        let _ = policy.exit(&mut mock::request(Get, format!("http://localhost{}", path).as_slice()),
+                            &mut res);
+        res.headers.cache_control
+    }
+
+    #[test]
+    fn caches_static_paths() {
+        assert_eq!(cache_control("/static/css/site.css"),
+                   Some("public, max-age=31536000".to_string()));
+    }
+
+    #[test]
+    fn defaults_dynamic_paths() {
+        assert_eq!(cache_control("/users/42"), Some("no-cache".to_string()));
+    }
+
+    #[test]
+    fn keeps_handler_cache_control() {
+        let mut policy = CachePolicy::new("no-cache");
+        let mut res = mock::response();
+        res.headers.cache_control = Some("private, max-age=60".to_string());
+
+        let _ = policy.exit(&mut mock::request(Get, "http://localhost/users/42"), &mut res);
+        assert_eq!(res.headers.cache_control, Some("private, max-age=60".to_string()));
+    }
+}
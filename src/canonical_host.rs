@@ -0,0 +1,114 @@
+//! Exposes the `CanonicalHost` middleware, which redirects requests
+//! made to other names of the site to its canonical host.
+
+use std::ascii::StrAsciiExt;
+
+use http::status::MovedPermanently;
+use url::Url;
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue, Unwind};
+
+/// `Middleware` which redirects requests whose host is not the canonical
+/// one to the same scheme, port, path and query on the canonical host,
+/// with a `301 Moved Permanently`.
+///
+/// Requests already on the canonical host, compared case-insensitively,
+/// pass through untouched:
+///
+/// ```ignore
+/// server.chain.link(CanonicalHost::new("example.com"));
+/// ```
+#[deriving(Clone)]
+pub struct CanonicalHost {
+    host: String
+}
+
+impl CanonicalHost {
+    /// Create a new `CanonicalHost` redirecting to `host`.
+    pub fn new(host: &str) -> CanonicalHost {
+        CanonicalHost { host: host.to_ascii_lower() }
+    }
+
+    // The equivalent of `url` on the canonical host, if it isn't on it.
+    fn canonical_url(&self, url: &Url) -> Option<Url> {
+        match url.domain() {
+            Some(domain) if domain.to_ascii_lower() != self.host => (),
+            _ => return None
+        }
+
+        let port = match url.port() {
+            Some(port) => format!(":{}", port),
+            None => String::new()
+        };
+        let path = url.serialize_path().unwrap_or("/".to_string());
+        let target = match url.query {
+            Some(ref query) => format!("{}://{}{}{}?{}", url.scheme, self.host, port, path, query),
+            None => format!("{}://{}{}{}", url.scheme, self.host, port, path)
+        };
+
+        Url::parse(target.as_slice()).ok()
+    }
+}
+
+impl Middleware for CanonicalHost {
+    fn enter(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        match self.canonical_url(&req.url) {
+            Some(url) => {
+                res.headers.location = Some(url);
+                res.serve(MovedPermanently, "");
+                Unwind
+            },
+            None => Continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::method::Get;
+    use http::status::MovedPermanently;
+
+    use super::CanonicalHost;
+    use super::super::mock;
+    use super::super::middleware::{Middleware, Continue, Unwind};
+
+    #[test]
+    fn redirects_other_hosts() {
+        let mut req = mock::request(Get, "http://www.example.com/a/b?c=d");
+        let mut res = mock::response();
+
+        match CanonicalHost::new("example.com").enter(&mut req, &mut res) {
+            Unwind => (),
+            _ => fail!("CanonicalHost did not unwind on another host.")
+        }
+
+        assert_eq!(res.status, Some(MovedPermanently));
+        assert_eq!(res.headers.location.unwrap().serialize().as_slice(),
+                   "http://example.com/a/b?c=d");
+    }
+
+    #[test]
+    fn keeps_scheme_and_port() {
+        let mut req = mock::request(Get, "https://www.example.com:8443/");
+        let mut res = mock::response();
+        let _ = CanonicalHost::new("example.com").enter(&mut req, &mut res);
+
+        assert_eq!(res.headers.location.unwrap().serialize().as_slice(),
+                   "https://example.com:8443/");
+    }
+
+    #[test]
+    fn passes_canonical_host() {
+        let mut req = mock::request(Get, "http://Example.com/a");
+        let mut res = mock::response();
+
+        match CanonicalHost::new("example.com").enter(&mut req, &mut res) {
+            Continue => (),
+            _ => fail!("CanonicalHost did not continue on the canonical host.")
+        }
+        assert!(res.status.is_none());
+        assert!(res.headers.location.is_none());
+    }
+}
@@ -11,10 +11,16 @@ use super::middleware::{Middleware, Status, Continue, Error};
 ///
 /// `Chains` are internal tools. Unless you want additional
 /// or unusual behavior such as enhanced debug logging you
-/// probably don't need to mess with `Chain` internals.
+/// probably don't need to mess with `Chain` internals. (`StackChain`
+/// can log its traversal itself, through `set_debug`.)
 ///
 /// That being said, custom `Chains` are extremely powerful as they
 /// allow you to completely control the resolution of `Middleware`.
+///
+/// `Chains` and their `Middleware` keep per-request state on `&mut self`,
+/// so a `Chain` must only dispatch one request at a time. The server
+/// upholds this by giving every connection its own clone of the `Chain`,
+/// which handles that connection's requests one after another.
 pub trait Chain: Send + Clone {
     /// `dispatch` will be called once per `Request` and is responsible
     /// for delegating the request to the correct `Middleware` and in the correct
@@ -75,10 +81,12 @@ impl Clone for Box<Chain + Send> {
 /// The default `chain` used by `Iron`.
 pub mod stackchain {
     use std::fmt::Show;
+    use std::intrinsics::type_name;
 
     use super::super::request::Request;
     use super::super::response::Response;
     use super::super::middleware::{Middleware, Continue, Unwind, Error, Status};
+    use super::super::logger::Logger;
 
     use super::Chain;
 
@@ -90,12 +98,97 @@ pub mod stackchain {
     ///
     /// If no `Middleware` return `Unwind` to indicate that they handled
     /// the request, then a 404 is automatically returned.
+    ///
+    /// A `StackChain` with no `Middleware` at all is most likely
+    /// misconfigured, and logs a warning for every request. In strict mode
+    /// it returns `Error` instead, so the request is answered with a 500.
+    ///
+    /// When a `Middleware` returns `Error`, the `on_error` handlers of the
+    /// `Middleware` before it are called from the innermost outwards, like
+    /// `exit`. `set_errors_outside_in` reverses this, so that outer handlers
+    /// see the error first.
+    ///
+    /// An `Error` returned from `exit` is logged, to the `Logger` given to
+    /// `set_error_logger` if any, and does not stop the remaining
+    /// `Middleware` from exiting. With `set_exit_errors_to_on_error`
+    /// their `on_error` handlers are called instead of `exit`.
+    ///
+    /// In debug mode, set with `set_debug`, every call to `enter`, `exit` and
+    /// `on_error` is logged with the index and type of its `Middleware`,
+    /// which shows the order requests actually take through the chain.
     #[deriving(Clone)]
     pub struct StackChain {
         /// The storage used by `StackChain` to hold all `Middleware`
         /// that have been `linked` to it.
         stack: Vec<Box<Middleware + Send>>,
         status: ChainStatus,
+        strict: bool,
+        errors_outside_in: bool,
+        exit_errors_to_on_error: bool,
+        // The type name of each `Middleware` in `stack`, for debug logging,
+        // kept at the same index as its `Middleware`.
+        names: Vec<&'static str>,
+        debug: Option<Box<Logger + Send>>,
+        error_logger: Option<Box<Logger + Send>>
+    }
+
+    impl StackChain {
+        /// Set whether an empty `StackChain` returns `Error` rather than
+        /// leaving the request unhandled.
+        pub fn set_strict(&mut self, strict: bool) {
+            self.strict = strict;
+        }
+
+        /// Set whether `on_error` handlers are called from the outermost
+        /// `Middleware` inwards, rather than from the innermost outwards.
+        pub fn set_errors_outside_in(&mut self, outside_in: bool) {
+            self.errors_outside_in = outside_in;
+        }
+
+        /// Set whether an `Error` returned from `exit` is passed to the
+        /// `on_error` handlers of the `Middleware` which have yet to exit,
+        /// instead of their `exit`.
+        pub fn set_exit_errors_to_on_error(&mut self, to_on_error: bool) {
+            self.exit_errors_to_on_error = to_on_error;
+        }
+
+        /// Rebuild the `StackChain` with each of its `Middleware` replaced by
+        /// the result of `f`, keeping their order.
+        ///
+        /// This is useful to wrap every `Middleware` in the same decorator at
+        /// startup, for instance to time each one.
+        pub fn map_middleware(self, f: |Box<Middleware + Send>| -> Box<Middleware + Send>) -> StackChain {
+            StackChain {
+                stack: self.stack.move_iter().map(|middleware| f(middleware)).collect(),
+                ..self
+            }
+        }
+
+        /// Log the `Error`s returned from `exit` to `logger`, rather than
+        /// through `error!`.
+        pub fn set_error_logger(&mut self, logger: Box<Logger + Send>) {
+            self.error_logger = Some(logger);
+        }
+
+        /// Log the traversal of every request to `logger`.
+        ///
+        /// Each line reads like `enter 2 app::Auth`. `Middleware` added
+        /// through `FromIterator` are boxed already, and are named
+        /// `Box<Middleware>`. `map_middleware` keeps the names of the
+        /// `Middleware` it replaces.
+        pub fn set_debug(&mut self, logger: Box<Logger + Send>) {
+            self.debug = Some(logger);
+        }
+    }
+
+    fn trace(debug: &mut Option<Box<Logger + Send>>, names: &[&'static str],
+             event: &str, i: uint) {
+        match *debug {
+            Some(ref mut logger) => {
+                logger.log(format!("{} {} {}", event, i, names[i]).as_slice());
+            },
+            None => ()
+        }
     }
 
     #[deriving(Clone)]
@@ -111,7 +204,16 @@ pub mod stackchain {
             // path through `Middleware` in reverse order than we did on the way in.
             self.status = Unhandled;
 
+            if self.stack.is_empty() {
+                warn!("Request dispatched to a StackChain with no middleware.");
+                if self.strict {
+                    self.status = Errored(0);
+                    return Error(box "StackChain has no middleware." as Box<Show>);
+                }
+            }
+
             'enter: for (i, middleware) in self.stack.mut_iter().enumerate() {
+                trace(&mut self.debug, self.names.as_slice(), "enter", i);
                 match middleware.enter(request, response) {
                     Unwind   => {
                         self.status = Unwound(i);
@@ -133,18 +235,46 @@ pub mod stackchain {
         fn chain_exit(&mut self,
                  request: &mut Request,
                  response: &mut Response) -> Status {
-            match self.status {
-                Unwound(i) => {
-                    for middleware in self.stack.mut_slice_to(i).mut_iter().rev() {
-                        let _ = middleware.exit(request, response);
-                    }
-                },
-                Unhandled => {
-                    for middleware in self.stack.mut_iter().rev() {
-                        let _ = middleware.exit(request, response);
-                    }
-                },
+            let to_on_error = self.exit_errors_to_on_error;
+            let (debug, names) = (&mut self.debug, self.names.as_slice());
+            let error_logger = &mut self.error_logger;
+            let passed = match self.status {
+                Unwound(i) => self.stack.mut_slice_to(i),
+                Unhandled => self.stack.as_mut_slice(),
                 Errored(_) => fail!("chain_exit called on a StackChain which Errored.")
+            };
+
+            // An `Error` from `exit` can't stop the response, which is already
+            // made, so it is logged and the remaining `Middleware` still see
+            // the request out, through `on_error` if `exit_errors_to_on_error`.
+            let mut error: Option<Box<Show>> = None;
+            for (i, middleware) in passed.mut_iter().enumerate().rev() {
+                if to_on_error {
+                    match error {
+                        Some(ref mut e) => {
+                            let e: &mut Show = *e;
+                            trace(debug, names, "on_error", i);
+                            middleware.on_error(request, response, e);
+                            continue;
+                        },
+                        None => ()
+                    }
+                }
+
+                trace(debug, names, "exit", i);
+                match middleware.exit(request, response) {
+                    Error(e) => {
+                        let shown: &Show = &*e;
+                        match *error_logger {
+                            Some(ref mut logger) => {
+                                logger.log(format!("Error in exit: {}", shown).as_slice())
+                            },
+                            None => error!("Error in exit: {}", shown)
+                        }
+                        if error.is_none() { error = Some(e); }
+                    },
+                    _ => ()
+                }
             }
 
             Continue
@@ -154,9 +284,17 @@ pub mod stackchain {
                       request: &mut Request,
                       response: &mut Response,
                       error: &mut Show) {
+            let (debug, names) = (&mut self.debug, self.names.as_slice());
             match self.status {
+                Errored(i) if self.errors_outside_in => {
+                    for (j, middleware) in self.stack.mut_slice_to(i).mut_iter().enumerate() {
+                        trace(debug, names, "on_error", j);
+                        let _ = middleware.on_error(request, response, error);
+                    }
+                },
                 Errored(i) => {
-                    for middleware in self.stack.mut_slice_to(i).mut_iter().rev() {
+                    for (j, middleware) in self.stack.mut_slice_to(i).mut_iter().enumerate().rev() {
+                        trace(debug, names, "on_error", j);
                         let _ = middleware.on_error(request, response, error);
                     }
                 },
@@ -166,6 +304,7 @@ pub mod stackchain {
 
         /// Add `Middleware` to the `Chain`.
         fn link<M: Middleware>(&mut self, middleware: M) {
+            self.names.push(unsafe { type_name::<M>() });
             self.stack.push(box middleware);
         }
 
@@ -173,16 +312,29 @@ pub mod stackchain {
         fn new() -> StackChain {
             StackChain {
                 stack: vec![],
-                status: Unhandled
+                status: Unhandled,
+                strict: false,
+                errors_outside_in: false,
+                exit_errors_to_on_error: false,
+                names: vec![],
+                debug: None,
+                error_logger: None
             }
         }
     }
 
     impl FromIterator<Box<Middleware + Send>> for StackChain {
         fn from_iter<T: Iterator<Box<Middleware + Send>>>(mut iterator: T) -> StackChain {
+            let stack: Vec<Box<Middleware + Send>> = iterator.collect();
             StackChain {
-                stack: iterator.collect(),
-                status: Unhandled
+                names: Vec::from_elem(stack.len(), "Box<Middleware>"),
+                stack: stack,
+                status: Unhandled,
+                strict: false,
+                errors_outside_in: false,
+                exit_errors_to_on_error: false,
+                debug: None,
+                error_logger: None
             }
         }
     }
@@ -192,7 +344,7 @@ pub mod stackchain {
         pub use super::*;
         pub use super::super::super::request::Request;
         pub use super::super::super::response::Response;
-        pub use super::super::super::middleware::{Middleware, Status, Continue, Unwind};
+        pub use super::super::super::middleware::{Middleware, Status, Continue, Unwind, Error};
         pub use std::sync::{Arc, Mutex};
 
         #[deriving(Clone)]
@@ -228,9 +380,11 @@ pub mod stackchain {
         }
 
         mod dispatch {
-            use super::{CallCount, Arc, Mutex, Stopper};
+            use super::{CallCount, Arc, Mutex, Stopper, Continue, Error};
             use super::super::StackChain;
             use super::super::super::Chain;
+            use super::super::super::super::mock;
+            use http::method::Get;
             use std::mem::uninitialized;
 
             #[test]
@@ -288,6 +442,29 @@ pub mod stackchain {
                     assert_eq!(*exit.lock(), 1);
                 }
             }
+
+            #[test]
+            fn empty_chain_is_unhandled() {
+                let mut testchain: StackChain = Chain::new();
+                let status = testchain.dispatch(&mut mock::request(Get, "http://localhost/"),
+                                                &mut mock::response());
+                match status {
+                    Continue => (),
+                    _ => fail!("An empty StackChain did not continue.")
+                }
+            }
+
+            #[test]
+            fn empty_strict_chain_errors() {
+                let mut testchain: StackChain = Chain::new();
+                testchain.set_strict(true);
+                let status = testchain.dispatch(&mut mock::request(Get, "http://localhost/"),
+                                                &mut mock::response());
+                match status {
+                    Error(_) => (),
+                    _ => fail!("An empty strict StackChain did not error.")
+                }
+            }
         }
 
         mod chain_enter {
@@ -329,6 +506,336 @@ pub mod stackchain {
             }
         }
 
+        mod concurrency {
+            use std::sync::Barrier;
+            use super::{Request, Response, Middleware, Status, Continue, Arc, Mutex};
+            use super::super::StackChain;
+            use super::super::super::Chain;
+            use super::super::super::super::mock;
+            use http::method::Get;
+
+            static TASKS: uint = 8;
+
+            // Keeps the id of the request it is handling, on itself as
+            // middleware commonly keep per-request state, or in `shared`
+            // to stand in for state every clone sees. All requests are
+            // held in `enter` until each of them has stored its id.
+            #[deriving(Clone)]
+            struct RequestId {
+                current: uint,
+                shared: Option<Arc<Mutex<uint>>>,
+                barrier: Arc<Barrier>,
+                mismatches: Arc<Mutex<uint>>
+            }
+
+            impl Middleware for RequestId {
+                fn enter(&mut self, req: &mut Request, _: &mut Response) -> Status {
+                    let id = *req.alloy.find::<uint>().unwrap();
+                    match self.shared {
+                        Some(ref shared) => *shared.lock() = id,
+                        None => self.current = id
+                    }
+                    self.barrier.wait();
+                    Continue
+                }
+
+                fn exit(&mut self, req: &mut Request, _: &mut Response) -> Status {
+                    let current = match self.shared {
+                        Some(ref shared) => *shared.lock(),
+                        None => self.current
+                    };
+                    if current != *req.alloy.find::<uint>().unwrap() {
+                        *self.mismatches.lock() += 1;
+                    }
+                    Continue
+                }
+            }
+
+            // Dispatch a request through a clone of a chain of a single
+            // `RequestId` from each of `TASKS` tasks at once, and count
+            // the requests which saw another's id in `exit`.
+            fn mismatches(shared: Option<Arc<Mutex<uint>>>) -> uint {
+                let mismatches = Arc::new(Mutex::new(0u));
+                let mut testchain: StackChain = Chain::new();
+                testchain.link(RequestId {
+                    current: 0,
+                    shared: shared,
+                    barrier: Arc::new(Barrier::new(TASKS)),
+                    mismatches: mismatches.clone()
+                });
+
+                let (tx, rx) = channel();
+                for task in range(0u, TASKS) {
+                    let mut chain = testchain.clone();
+                    let tx = tx.clone();
+                    spawn(proc() {
+                        let mut req = mock::request(Get, "http://localhost/");
+                        req.alloy.insert::<uint>(task);
+                        let _ = chain.dispatch(&mut req, &mut mock::response());
+                        tx.send(());
+                    });
+                }
+
+                for _ in range(0u, TASKS) { rx.recv(); }
+                let count = *mismatches.lock();
+                count
+            }
+
+            #[test]
+            fn clones_do_not_share_request_state() {
+                assert_eq!(mismatches(None), 0);
+            }
+
+            #[test]
+            fn shared_request_state_is_detected() {
+                // Only the request whose id was stored last sees its own.
+                assert_eq!(mismatches(Some(Arc::new(Mutex::new(0u)))), TASKS - 1);
+            }
+        }
+
+        mod debug {
+            use super::Stopper;
+            use super::super::StackChain;
+            use super::super::super::Chain;
+            use super::super::super::super::mock;
+            use super::super::super::super::middleware::Middleware;
+            use http::method::Get;
+
+            #[deriving(Clone)]
+            struct Auth;
+
+            impl Middleware for Auth {}
+
+            #[deriving(Clone)]
+            struct Session;
+
+            impl Middleware for Session {}
+
+            #[test]
+            fn logs_traversal() {
+                let logger = mock::CaptureLogger::new();
+                let mut testchain: StackChain = Chain::new();
+                testchain.link(Session);
+                testchain.link(Auth);
+                testchain.link(Stopper);
+                testchain.set_debug(logger.boxed());
+
+                let _ = testchain.dispatch(&mut mock::request(Get, "http://localhost/"),
+                                           &mut mock::response());
+
+                let lines = logger.lines();
+                let expected = [("enter 0 ", "Session"), ("enter 1 ", "Auth"), ("enter 2 ", "Stopper"),
+                                ("exit 1 ", "Auth"), ("exit 0 ", "Session")];
+                assert_eq!(lines.len(), expected.len());
+                for (line, &(event, name)) in lines.iter().zip(expected.iter()) {
+                    assert!(line.as_slice().starts_with(event), "{}", line);
+                    assert!(line.as_slice().ends_with(name), "{}", line);
+                }
+            }
+
+            #[test]
+            fn names_middleware_linked_after_from_iter() {
+                // As `Router::route_with` builds the chain of a route.
+                let logger = mock::CaptureLogger::new();
+                let mut testchain: StackChain = vec![box Session as Box<Middleware + Send>]
+                    .move_iter().collect();
+                testchain.link(Stopper);
+                testchain.set_debug(logger.boxed());
+
+                let _ = testchain.dispatch(&mut mock::request(Get, "http://localhost/"),
+                                           &mut mock::response());
+
+                let lines = logger.lines();
+                assert_eq!(lines.len(), 2);
+                assert_eq!(lines[0].as_slice(), "enter 0 Box<Middleware>");
+                assert!(lines[1].as_slice().starts_with("enter 1 "), "{}", lines[1]);
+                assert!(lines[1].as_slice().ends_with("Stopper"), "{}", lines[1]);
+            }
+        }
+
+        mod map_middleware {
+            use super::{Request, Response, Middleware, Status, Stopper, Arc, Mutex};
+            use super::super::StackChain;
+            use super::super::super::Chain;
+            use super::super::super::super::mock;
+            use http::method::Get;
+
+            // Counts the requests entering the middleware it wraps.
+            #[deriving(Clone)]
+            struct Counted {
+                inner: Box<Middleware + Send>,
+                count: Arc<Mutex<uint>>
+            }
+
+            impl Middleware for Counted {
+                fn enter(&mut self, req: &mut Request, res: &mut Response) -> Status {
+                    *self.count.lock() += 1;
+                    self.inner.enter(req, res)
+                }
+
+                fn exit(&mut self, req: &mut Request, res: &mut Response) -> Status {
+                    self.inner.exit(req, res)
+                }
+            }
+
+            #[deriving(Clone)]
+            struct Noop;
+
+            impl Middleware for Noop {}
+
+            #[test]
+            fn wraps_every_middleware() {
+                let count = Arc::new(Mutex::new(0u));
+                let mut testchain: StackChain = Chain::new();
+                testchain.link(Noop);
+                testchain.link(Noop);
+                testchain.link(Stopper);
+
+                let mut testchain = testchain.map_middleware(|middleware| {
+                    box Counted { inner: middleware, count: count.clone() } as Box<Middleware + Send>
+                });
+
+                let _ = testchain.dispatch(&mut mock::request(Get, "http://localhost/"),
+                                           &mut mock::response());
+                assert_eq!(*count.lock(), 3);
+
+                let _ = testchain.dispatch(&mut mock::request(Get, "http://localhost/"),
+                                           &mut mock::response());
+                assert_eq!(*count.lock(), 6);
+            }
+        }
+
+        mod chain_error {
+            use std::fmt::Show;
+            use super::{Request, Response, Middleware, Status, Error, Arc, Mutex};
+            use super::super::StackChain;
+            use super::super::super::Chain;
+            use super::super::super::super::mock;
+            use http::method::Get;
+
+            // Logs its name when its `on_error` is called.
+            #[deriving(Clone)]
+            struct Handler {
+                name: &'static str,
+                log: Arc<Mutex<Vec<&'static str>>>
+            }
+
+            impl Middleware for Handler {
+                fn on_error(&mut self, _: &mut Request, _: &mut Response, _: &mut Show) {
+                    self.log.lock().push(self.name);
+                }
+            }
+
+            #[deriving(Clone)]
+            struct Fails;
+
+            impl Middleware for Fails {
+                fn enter(&mut self, _: &mut Request, _: &mut Response) -> Status {
+                    Error(box "failed" as Box<Show>)
+                }
+            }
+
+            fn error_order(outside_in: bool) -> Vec<&'static str> {
+                let log = Arc::new(Mutex::new(vec![]));
+                let mut testchain: StackChain = Chain::new();
+                testchain.set_errors_outside_in(outside_in);
+                testchain.link(Handler { name: "outer", log: log.clone() });
+                testchain.link(Handler { name: "inner", log: log.clone() });
+                testchain.link(Fails);
+
+                let _ = testchain.dispatch(&mut mock::request(Get, "http://localhost/"),
+                                           &mut mock::response());
+                let order = log.lock().clone();
+                order
+            }
+
+            #[test]
+            fn calls_on_error_inside_out_by_default() {
+                assert_eq!(error_order(false), vec!["inner", "outer"]);
+            }
+
+            #[test]
+            fn calls_on_error_outside_in() {
+                assert_eq!(error_order(true), vec!["outer", "inner"]);
+            }
+        }
+
+        mod exit_error {
+            use std::fmt::Show;
+            use super::{Request, Response, Middleware, Status, Continue, Unwind, Error, Arc, Mutex};
+            use super::super::StackChain;
+            use super::super::super::Chain;
+            use super::super::super::super::mock;
+            use super::super::super::super::middleware::FromFn;
+            use http::method::Get;
+            use http::status::Ok;
+
+            // Logs which of its handlers are called.
+            #[deriving(Clone)]
+            struct Outer { log: Arc<Mutex<Vec<&'static str>>> }
+
+            impl Middleware for Outer {
+                fn exit(&mut self, _: &mut Request, _: &mut Response) -> Status {
+                    self.log.lock().push("exit");
+                    Continue
+                }
+
+                fn on_error(&mut self, _: &mut Request, _: &mut Response, _: &mut Show) {
+                    self.log.lock().push("on_error");
+                }
+            }
+
+            #[deriving(Clone)]
+            struct FailsOnExit;
+
+            impl Middleware for FailsOnExit {
+                fn exit(&mut self, _: &mut Request, _: &mut Response) -> Status {
+                    Error(box "failed on exit" as Box<Show>)
+                }
+            }
+
+            fn handled(_: &mut Request, res: &mut Response) -> Status {
+                res.serve(Ok, "Hello");
+                Unwind
+            }
+
+            fn outer_calls(to_on_error: bool, logger: &mock::CaptureLogger)
+                -> (Vec<&'static str>, Response) {
+                let log = Arc::new(Mutex::new(vec![]));
+                let mut testchain: StackChain = Chain::new();
+                testchain.set_exit_errors_to_on_error(to_on_error);
+                testchain.set_error_logger(logger.boxed());
+                testchain.link(Outer { log: log.clone() });
+                testchain.link(FailsOnExit);
+                testchain.link(FromFn::new(handled));
+
+                let mut res = mock::response();
+                let _ = testchain.dispatch(&mut mock::request(Get, "http://localhost/"), &mut res);
+                let calls = log.lock().clone();
+                (calls, res)
+            }
+
+            #[test]
+            fn keeps_exiting_after_exit_error() {
+                let logger = mock::CaptureLogger::new();
+                let (calls, mut res) = outer_calls(false, &logger);
+                assert_eq!(calls, vec!["exit"]);
+                assert_eq!(res.status, Some(Ok));
+                assert_eq!(res.body.read_to_end().unwrap(), b"Hello".to_vec());
+                assert_eq!(logger.lines(), vec!["Error in exit: failed on exit".to_string()]);
+            }
+
+            #[test]
+            fn routes_exit_error_to_on_error() {
+                let logger = mock::CaptureLogger::new();
+                let (calls, mut res) = outer_calls(true, &logger);
+                assert_eq!(calls, vec!["on_error"]);
+                assert_eq!(res.status, Some(Ok));
+                assert_eq!(res.body.read_to_end().unwrap(), b"Hello".to_vec());
+                assert_eq!(logger.lines(), vec!["Error in exit: failed on exit".to_string()]);
+            }
+        }
+
         mod chain_exit {
             use super::{CallCount, Arc, Mutex, Stopper};
             use super::super::{StackChain, Unwound};
@@ -395,13 +902,10 @@ pub mod stackchain {
                             testchain.link(Noop);
                         }
                         testchain.link(Stopper);
+                        let mut req = mock::request(Get, "http://localhost:3000/");
+                        let mut res = mock::response();
                         b.iter(|| {
-                            black_box(unsafe {
-                                let _ = testchain.$method(
-                                    uninitialized(),
-                                    uninitialized()
-                                );
-                            })
+                            black_box(testchain.$method(&mut req, &mut res))
                         });
                     }
                 }
@@ -410,9 +914,10 @@ pub mod stackchain {
             macro_rules! bench_method (
                 ($method:ident) => {
                     mod $method {
-                        use std::mem::uninitialized;
                         use test::{Bencher, black_box};
+                        use http::method::Get;
                         use super::{Noop, Stopper};
+                        use super::super::super::super::super::mock;
                         use super::super::super::StackChain;
                         use super::super::super::super::Chain;
 
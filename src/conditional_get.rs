@@ -0,0 +1,265 @@
+//! Exposes the `ConditionalGet` middleware, which answers conditional
+//! requests for unchanged resources with `304 Not Modified`, and refuses
+//! writes to changed ones with `412 Precondition Failed`.
+
+use std::ascii::StrAsciiExt;
+
+use http::method::{Get, Head};
+use http::status::{NotModified, PreconditionFailed};
+use OkStatus = http::status::Ok;
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue, Unwind};
+
+/// `Middleware` which replaces a `200 OK` response to a `GET` or `HEAD`
+/// with an empty `304 Not Modified` when the client's cached copy is
+/// still fresh, see `Response::is_fresh`.
+///
+/// Handlers only need to set `Last-Modified` or `ETag` from their data
+/// for any response, dynamic or not, to benefit:
+///
+/// ```ignore
+/// server.chain.link(ConditionalGet::new());
+///
+/// fn show_post(req: &mut Request, res: &mut Response) -> Status {
+///     let post = load_post(req);
+///     res.headers.last_modified = Some(post.updated_at);
+///     res.serve(Ok, render(&post));
+///     Unwind
+/// }
+/// ```
+///
+/// The check is made in `exit`, after the response has been built.
+/// Streamed responses are left alone.
+///
+/// Writes have to be checked before the handler runs, so for methods
+/// other than `GET` and `HEAD` the current `ETag` of the target is looked
+/// up with the function given to `with_etags`. A request whose `If-Match`
+/// doesn't name that tag, or whose `If-None-Match` does, is answered with
+/// `412 Precondition Failed` and never reaches the handler:
+///
+/// ```ignore
+/// fn post_etag(req: &Request) -> Option<String> {
+///     find_post(req).map(|post| format!("\"{}\"", post.version))
+/// }
+///
+/// server.chain.link(ConditionalGet::with_etags(post_etag));
+/// ```
+pub struct ConditionalGet {
+    etags: Option<fn(&Request) -> Option<String>>
+}
+
+impl ConditionalGet {
+    /// Create a `ConditionalGet` which only answers `GET` and `HEAD`.
+    pub fn new() -> ConditionalGet {
+        ConditionalGet { etags: None }
+    }
+
+    /// Create a `ConditionalGet` which also checks the preconditions of
+    /// other methods, against the `ETag` returned by `etags`.
+    ///
+    /// `etags` returns `None` when the target doesn't exist.
+    pub fn with_etags(etags: fn(&Request) -> Option<String>) -> ConditionalGet {
+        ConditionalGet { etags: Some(etags) }
+    }
+}
+
+impl Clone for ConditionalGet {
+    fn clone(&self) -> ConditionalGet {
+        ConditionalGet { etags: self.etags }
+    }
+}
+
+impl Middleware for ConditionalGet {
+    fn enter(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        if req.method == Get || req.method == Head {
+            return Continue;
+        }
+        let current = match self.etags {
+            Some(etags) => etags(req),
+            None => return Continue
+        };
+
+        let pairs = req.header_pairs();
+        let header = |name: &str| {
+            pairs.iter().find(|&&(ref n, _)| n.as_slice().eq_ignore_ascii_case(name))
+                 .map(|&(_, ref v)| v.as_slice())
+        };
+        let failed = match (header("If-Match"), header("If-None-Match")) {
+            (Some(tags), _) => !matches(tags, &current, false),
+            (None, Some(tags)) => matches(tags, &current, true),
+            (None, None) => false
+        };
+
+        if failed {
+            res.serve(PreconditionFailed, "");
+            return Unwind;
+        }
+        Continue
+    }
+
+    fn exit(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        let cacheable = (req.method == Get || req.method == Head) && res.status == Some(OkStatus);
+        if cacheable && !res.is_streaming() && res.is_fresh(req) {
+            res.serve(NotModified, "");
+        }
+        Continue
+    }
+}
+
+// Whether a list of entity tags names `current`. If-Match uses the strong
+// comparison, where weak tags never match, and If-None-Match the weak one,
+// which ignores the `W/` prefix on either side (RFC 7232, section 2.3.2).
+fn matches(tags: &str, current: &Option<String>, weak: bool) -> bool {
+    let opaque = |tag: &str| -> Option<String> {
+        if !tag.starts_with("W/") { Some(tag.to_string()) }
+        else if weak { Some(tag.slice_from(2).to_string()) }
+        else { None }
+    };
+
+    match *current {
+        Some(ref etag) => tags.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || match (opaque(tag), opaque(etag.as_slice())) {
+                (Some(tag), Some(etag)) => tag == etag,
+                _ => false
+            }
+        }),
+        None => false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::{Timespec, at_utc};
+    use http::method::{Get, Post, Put};
+    use http::status::{NotModified, PreconditionFailed};
+    use OkStatus = http::status::Ok;
+
+    use super::ConditionalGet;
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::middleware::{Status, Unwind, FromFn};
+
+    fn dynamic(_: &mut Request, res: &mut Response) -> Status {
+        res.headers.last_modified = Some(at_utc(Timespec::new(1400000000, 0)));
+        res.set_header("ETag", "\"v1\"");
+        res.serve(OkStatus, "Post");
+        Unwind
+    }
+
+    fn respond(mut req: Request) -> Response {
+        let mut chain: StackChain = Chain::new();
+        chain.link(ConditionalGet::new());
+        chain.link(FromFn::new(dynamic));
+
+        let mut res = mock::response();
+        let _ = chain.dispatch(&mut req, &mut res);
+        res
+    }
+
+    fn current_etag(_: &Request) -> Option<String> {
+        Some("\"v2\"".to_string())
+    }
+
+    fn put(if_match: &str) -> Response {
+        put_with("If-Match", if_match)
+    }
+
+    fn put_with(header: &str, tags: &str) -> Response {
+        let mut req = mock::request(Put, "http://localhost/post");
+        let _ = req.headers.extensions.insert(header.to_string(), tags.to_string());
+
+        let mut chain: StackChain = Chain::new();
+        chain.link(ConditionalGet::with_etags(current_etag));
+        chain.link(FromFn::new(dynamic));
+
+        let mut res = mock::response();
+        let _ = chain.dispatch(&mut req, &mut res);
+        res
+    }
+
+    #[test]
+    fn not_modified_since_last_modified() {
+        let mut req = mock::request(Get, "http://localhost/post");
+        req.headers.if_modified_since = Some(at_utc(Timespec::new(1400000000, 0)));
+
+        let mut res = respond(req);
+        assert_eq!(res.status, Some(NotModified));
+        assert_eq!(res.body.read_to_end().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn sends_no_body_headers_when_not_modified() {
+        let mut req = mock::request(Get, "http://localhost/post");
+        req.headers.if_modified_since = Some(at_utc(Timespec::new(1400000000, 0)));
+
+        let mut res = respond(req);
+        let mut wire = vec![];
+        res.write_to(&mock::request(Get, "http://localhost/post"), &mut wire).unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(wire.as_slice().starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(wire.as_slice().contains("\r\nETag: \"v1\"\r\n"));
+        assert!(!wire.as_slice().contains("Content-Length"));
+        assert!(!wire.as_slice().contains("Content-Type"));
+        assert!(wire.as_slice().ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn modified_since_earlier_date() {
+        let mut req = mock::request(Get, "http://localhost/post");
+        req.headers.if_modified_since = Some(at_utc(Timespec::new(1300000000, 0)));
+
+        let mut res = respond(req);
+        assert_eq!(res.status, Some(OkStatus));
+        assert_eq!(res.body.read_to_end().unwrap(), b"Post".to_vec());
+    }
+
+    #[test]
+    fn matches_etag_over_date() {
+        let mut req = mock::request(Get, "http://localhost/post");
+        let _ = req.headers.extensions.insert("If-None-Match".to_string(), "\"v0\", W/\"v1\"".to_string());
+        assert_eq!(respond(req).status, Some(NotModified));
+
+        let mut req = mock::request(Get, "http://localhost/post");
+        let _ = req.headers.extensions.insert("If-None-Match".to_string(), "\"v0\"".to_string());
+        req.headers.if_modified_since = Some(at_utc(Timespec::new(1400000000, 0)));
+        assert_eq!(respond(req).status, Some(OkStatus));
+    }
+
+    #[test]
+    fn ignores_unsafe_methods() {
+        let mut req = mock::request(Post, "http://localhost/post");
+        req.headers.if_modified_since = Some(at_utc(Timespec::new(1400000000, 0)));
+        assert_eq!(respond(req).status, Some(OkStatus));
+    }
+
+    #[test]
+    fn stale_if_match_fails() {
+        let mut res = put("\"v1\"");
+        assert_eq!(res.status, Some(PreconditionFailed));
+        assert_eq!(res.body.read_to_end().unwrap(), vec![]);
+
+        assert_eq!(put("W/\"v2\"").status, Some(PreconditionFailed));
+    }
+
+    #[test]
+    fn matching_if_match_proceeds() {
+        let mut res = put("\"v1\", \"v2\"");
+        assert_eq!(res.status, Some(OkStatus));
+        assert_eq!(res.body.read_to_end().unwrap(), b"Post".to_vec());
+
+        assert_eq!(put("*").status, Some(OkStatus));
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        assert_eq!(put_with("If-None-Match", "W/\"v2\"").status, Some(PreconditionFailed));
+        assert_eq!(put_with("If-None-Match", "\"v2\"").status, Some(PreconditionFailed));
+        assert_eq!(put_with("If-None-Match", "W/\"v1\"").status, Some(OkStatus));
+    }
+}
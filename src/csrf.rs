@@ -0,0 +1,251 @@
+//! Exposes the `Csrf` middleware, which protects form submissions
+//! against cross-site request forgery.
+
+use std::ascii::StrAsciiExt;
+
+use http::method::{Get, Head, Options, Trace};
+use http::status::Forbidden;
+use url::form_urlencoded;
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue, Unwind};
+use super::entropy::Entropy;
+
+/// The CSRF token of the current request.
+///
+/// `Csrf` stores it in `Request::alloy` so that handlers and templates
+/// can embed it in their forms:
+///
+/// ```ignore
+/// let CsrfToken(ref token) = *req.alloy.find::<CsrfToken>().unwrap();
+/// ```
+#[deriving(Clone, PartialEq, Show)]
+pub struct CsrfToken(pub String);
+
+/// Where `Csrf` keeps the token it compares submissions against.
+///
+/// The default, `CookieStore`, implements the double-submit-cookie
+/// pattern. A store backed by a server-side session implements the
+/// synchronizer-token pattern instead.
+pub trait TokenStore: Send + Clone {
+    /// The token stored for the client making `req`, if any.
+    fn load(&self, req: &Request) -> Option<String>;
+
+    /// Store a freshly generated `token` for the client making `req`.
+    fn store(&mut self, req: &mut Request, res: &mut Response, token: &str);
+
+    // Helper function to clone the TokenStore.
+    #[doc(hidden)]
+    fn clone_box(&self) -> Box<TokenStore + Send> { box self.clone() as Box<TokenStore + Send> }
+}
+
+impl Clone for Box<TokenStore + Send> {
+    fn clone(&self) -> Box<TokenStore + Send> { self.clone_box() }
+}
+
+/// A `TokenStore` which keeps the token in a cookie.
+#[deriving(Clone)]
+pub struct CookieStore {
+    name: String
+}
+
+impl CookieStore {
+    /// Create a new `CookieStore` using the cookie `name`.
+    pub fn new(name: &str) -> CookieStore {
+        CookieStore { name: name.to_string() }
+    }
+}
+
+impl TokenStore for CookieStore {
+    fn load(&self, req: &Request) -> Option<String> {
+        for (name, value) in req.headers.extensions.iter() {
+            if !name.as_slice().eq_ignore_ascii_case("Cookie") { continue }
+
+            for pair in value.as_slice().split(';') {
+                let mut parts = pair.trim().splitn('=', 1);
+                if parts.next() == Some(self.name.as_slice()) {
+                    return parts.next().map(|token| token.to_string());
+                }
+            }
+        }
+        None
+    }
+
+    fn store(&mut self, _: &mut Request, res: &mut Response, token: &str) {
+        res.set_cookie(format!("{}={}; Path=/", self.name, token).as_slice());
+    }
+}
+
+/// `Middleware` which rejects unsafe requests not carrying the client's
+/// CSRF token with a `403 Forbidden`.
+///
+/// On `GET`, `HEAD`, `OPTIONS` and `TRACE` requests `Csrf` issues a token
+/// if the client has none, and makes it available as a `CsrfToken`. Every
+/// other request must submit the same token, in the `X-CSRF-Token` header
+/// or the `csrf_token` field of its form body:
+///
+/// ```ignore
+/// server.chain.link(Csrf::new());
+/// ```
+///
+/// Tokens are generated from the request's `Entropy`.
+#[deriving(Clone)]
+pub struct Csrf {
+    store: Box<TokenStore + Send>,
+    header: String,
+    field: String
+}
+
+impl Csrf {
+    /// Create a new `Csrf` keeping tokens in the `csrf_token` cookie.
+    pub fn new() -> Csrf {
+        Csrf::with_store(box CookieStore::new("csrf_token") as Box<TokenStore + Send>)
+    }
+
+    /// Create a new `Csrf` keeping tokens in `store`.
+    pub fn with_store(store: Box<TokenStore + Send>) -> Csrf {
+        Csrf {
+            store: store,
+            header: "X-CSRF-Token".to_string(),
+            field: "csrf_token".to_string()
+        }
+    }
+
+    /// Read submitted tokens from the form field `field` instead of `csrf_token`.
+    pub fn field(mut self, field: &str) -> Csrf {
+        self.field = field.to_string();
+        self
+    }
+
+    fn submitted(&self, req: &Request) -> Option<String> {
+        for (name, value) in req.headers.extensions.iter() {
+            if name.as_slice().eq_ignore_ascii_case(self.header.as_slice()) {
+                return Some(value.clone());
+            }
+        }
+
+        form_urlencoded::parse(req.body.as_bytes()).move_iter()
+            .find(|&(ref name, _)| name.as_slice() == self.field.as_slice())
+            .map(|(_, value)| value)
+    }
+}
+
+// Compare tokens in time independent of where they first differ.
+fn tokens_match(a: &str, b: &str) -> bool {
+    if a.len() != b.len() { return false }
+    a.bytes().zip(b.bytes()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl Middleware for Csrf {
+    fn enter(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        let stored = self.store.load(req);
+
+        match req.method {
+            Get | Head | Options | Trace => {
+                let token = match stored {
+                    Some(token) => token,
+                    None => {
+                        let token = match req.alloy.find::<Entropy>() {
+                            Some(entropy) => entropy.hex_id(),
+                            None => Entropy::new().hex_id()
+                        };
+                        self.store.store(req, res, token.as_slice());
+                        token
+                    }
+                };
+                req.alloy.insert(CsrfToken(token));
+                Continue
+            },
+            _ => {
+                match (stored, self.submitted(req)) {
+                    (Some(ref stored), Some(ref submitted))
+                        if tokens_match(stored.as_slice(), submitted.as_slice()) => {
+                        req.alloy.insert(CsrfToken(stored.clone()));
+                        Continue
+                    },
+                    _ => {
+                        res.serve(Forbidden, "Forbidden");
+                        Unwind
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::method::{Get, Post};
+    use http::status::Forbidden;
+
+    use super::{Csrf, CsrfToken};
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::middleware::{Status, Unwind, FromFn};
+    use super::super::entropy::Entropy;
+
+    fn handled(req: &mut Request, res: &mut Response) -> Status {
+        let CsrfToken(ref token) = *req.alloy.find::<CsrfToken>().unwrap();
+        res.serve(::http::status::Ok, token.as_slice());
+        Unwind
+    }
+
+    fn dispatch(req: &mut Request) -> Response {
+        let mut chain: StackChain = Chain::new();
+        chain.link(Csrf::new());
+        chain.link(FromFn::new(handled));
+
+        let mut res = mock::response();
+        let _ = chain.dispatch(req, &mut res);
+        res
+    }
+
+    fn post(cookie: &str, body: &str) -> Request {
+        let mut req = mock::request(Post, "http://localhost/posts");
+        let _ = req.headers.extensions.insert("Cookie".to_string(), cookie.to_string());
+        req.body = body.to_string();
+        req
+    }
+
+    #[test]
+    fn get_issues_token() {
+        let mut req = mock::request(Get, "http://localhost/posts/new");
+        req.alloy.insert(Entropy::seeded([1]));
+        let res = dispatch(&mut req);
+
+        let token = Entropy::seeded([1]).hex_id();
+        assert_eq!(req.alloy.find::<CsrfToken>(), Some(&CsrfToken(token.clone())));
+        assert_eq!(res.cookies(), [format!("csrf_token={}; Path=/", token)].as_slice());
+    }
+
+    #[test]
+    fn get_keeps_existing_token() {
+        let mut req = mock::request(Get, "http://localhost/posts/new");
+        let _ = req.headers.extensions.insert("Cookie".to_string(), "csrf_token=abc".to_string());
+        let res = dispatch(&mut req);
+
+        assert_eq!(req.alloy.find::<CsrfToken>(), Some(&CsrfToken("abc".to_string())));
+        assert!(!res.has_header("Set-Cookie"));
+    }
+
+    #[test]
+    fn post_with_token_passes() {
+        let res = dispatch(&mut post("theme=dark; csrf_token=abc", "title=x&csrf_token=abc"));
+        assert_eq!(res.status, Some(::http::status::Ok));
+
+        let mut req = post("csrf_token=abc", "");
+        let _ = req.headers.extensions.insert("X-CSRF-Token".to_string(), "abc".to_string());
+        assert_eq!(dispatch(&mut req).status, Some(::http::status::Ok));
+    }
+
+    #[test]
+    fn post_with_wrong_or_absent_token_is_forbidden() {
+        assert_eq!(dispatch(&mut post("csrf_token=abc", "csrf_token=abd")).status, Some(Forbidden));
+        assert_eq!(dispatch(&mut post("csrf_token=abc", "title=x")).status, Some(Forbidden));
+        assert_eq!(dispatch(&mut post("", "csrf_token=abc")).status, Some(Forbidden));
+    }
+}
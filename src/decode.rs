@@ -0,0 +1,176 @@
+//! Decoding query strings and form bodies into `Decodable` types.
+
+use std::from_str::FromStr;
+use serialize::{Decodable, Decoder};
+
+/// Why a query string or form body could not be decoded.
+///
+/// These are the client's mistakes, so a request failing to decode
+/// should usually be answered with a `400 Bad Request`.
+#[deriving(Clone, PartialEq, Show)]
+pub enum DecodeError {
+    /// A required field, named here, is missing.
+    MissingField(String),
+
+    /// The field named first has a value, given second, which does not
+    /// parse as its type.
+    BadValue(String, String),
+
+    /// The type being decoded into can't come from a flat list of fields.
+    UnsupportedType(String)
+}
+
+/// A `Decoder` reading the fields of a struct from `name=value` pairs.
+///
+/// Fields of any type implementing `FromStr` are parsed from their value,
+/// `Option` fields are `None` when missing, and `Vec` fields collect
+/// every value given for their name. Unit-like enums are decoded from
+/// the name of their variant.
+pub struct FormDecoder {
+    pairs: Vec<(String, String)>,
+    field: String,
+    value: Option<String>,
+    values: Vec<String>
+}
+
+impl FormDecoder {
+    /// Create a new `FormDecoder` reading from `pairs`.
+    pub fn new(pairs: Vec<(String, String)>) -> FormDecoder {
+        FormDecoder { pairs: pairs, field: String::new(), value: None, values: vec![] }
+    }
+
+    fn value(&self) -> Result<String, DecodeError> {
+        match self.value {
+            Some(ref value) => Ok(value.clone()),
+            None => Err(MissingField(self.field.clone()))
+        }
+    }
+
+    fn parse<T: FromStr>(&mut self) -> Result<T, DecodeError> {
+        let value = try!(self.value());
+        match from_str(value.as_slice()) {
+            Some(parsed) => Ok(parsed),
+            None => Err(BadValue(self.field.clone(), value))
+        }
+    }
+}
+
+/// Decode `pairs` into a `T`.
+pub fn decode<T: Decodable<FormDecoder, DecodeError>>(pairs: Vec<(String, String)>)
+                                                     -> Result<T, DecodeError> {
+    Decodable::decode(&mut FormDecoder::new(pairs))
+}
+
+macro_rules! read_parsed (
+    ($($name:ident -> $ty:ty),+) => (
+        $(fn $name(&mut self) -> Result<$ty, DecodeError> { self.parse() })+
+    )
+)
+
+impl Decoder<DecodeError> for FormDecoder {
+    fn read_nil(&mut self) -> Result<(), DecodeError> { Ok(()) }
+
+    read_parsed!(read_uint -> uint, read_u64 -> u64, read_u32 -> u32, read_u16 -> u16, read_u8 -> u8,
+                 read_int -> int, read_i64 -> i64, read_i32 -> i32, read_i16 -> i16, read_i8 -> i8,
+                 read_bool -> bool, read_f64 -> f64, read_f32 -> f32, read_char -> char)
+
+    fn read_str(&mut self) -> Result<String, DecodeError> { self.value() }
+
+    fn read_enum<T>(&mut self, _: &str,
+                    f: |&mut FormDecoder| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        f(self)
+    }
+
+    fn read_enum_variant<T>(&mut self, names: &[&str],
+                            f: |&mut FormDecoder, uint| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        let value = try!(self.value());
+        match names.iter().position(|name| *name == value.as_slice()) {
+            Some(i) => f(self, i),
+            None => Err(BadValue(self.field.clone(), value))
+        }
+    }
+
+    fn read_enum_variant_arg<T>(&mut self, _: uint,
+                                _: |&mut FormDecoder| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        Err(self.error("enum variants with fields"))
+    }
+
+    fn read_enum_struct_variant<T>(&mut self, _: &[&str],
+                                   _: |&mut FormDecoder, uint| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        Err(self.error("enum variants with fields"))
+    }
+
+    fn read_enum_struct_variant_field<T>(&mut self, _: &str, _: uint,
+                                         _: |&mut FormDecoder| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        Err(self.error("enum variants with fields"))
+    }
+
+    fn read_struct<T>(&mut self, _: &str, _: uint,
+                      f: |&mut FormDecoder| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        f(self)
+    }
+
+    fn read_struct_field<T>(&mut self, name: &str, _: uint,
+                            f: |&mut FormDecoder| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        self.field = name.to_string();
+        self.values = self.pairs.iter()
+            .filter(|&&(ref key, _)| key.as_slice() == name)
+            .map(|&(_, ref value)| value.clone())
+            .collect();
+        self.value = self.values.last().map(|value| value.clone());
+        f(self)
+    }
+
+    fn read_tuple<T>(&mut self, _: |&mut FormDecoder, uint| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        Err(self.error("tuples"))
+    }
+
+    fn read_tuple_arg<T>(&mut self, _: uint,
+                         _: |&mut FormDecoder| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        Err(self.error("tuples"))
+    }
+
+    fn read_tuple_struct<T>(&mut self, _: &str,
+                            _: |&mut FormDecoder, uint| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        Err(self.error("tuple structs"))
+    }
+
+    fn read_tuple_struct_arg<T>(&mut self, _: uint,
+                                _: |&mut FormDecoder| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        Err(self.error("tuple structs"))
+    }
+
+    fn read_option<T>(&mut self, f: |&mut FormDecoder, bool| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        let present = self.value.is_some();
+        f(self, present)
+    }
+
+    fn read_seq<T>(&mut self, f: |&mut FormDecoder, uint| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        let len = self.values.len();
+        f(self, len)
+    }
+
+    fn read_seq_elt<T>(&mut self, i: uint,
+                       f: |&mut FormDecoder| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        self.value = Some(self.values.get(i).clone());
+        f(self)
+    }
+
+    fn read_map<T>(&mut self, _: |&mut FormDecoder, uint| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        Err(self.error("maps"))
+    }
+
+    fn read_map_elt_key<T>(&mut self, _: uint,
+                           _: |&mut FormDecoder| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        Err(self.error("maps"))
+    }
+
+    fn read_map_elt_val<T>(&mut self, _: uint,
+                           _: |&mut FormDecoder| -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+        Err(self.error("maps"))
+    }
+
+    fn error(&mut self, err: &str) -> DecodeError {
+        UnsupportedType(err.to_string())
+    }
+}
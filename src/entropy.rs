@@ -0,0 +1,81 @@
+//! Exposes `Entropy`, the source of randomness shared by a server
+//! and the requests it handles.
+
+use std::rand::{Rng, StdRng, SeedableRng};
+use std::sync::{Arc, Mutex};
+
+/// A shared random number generator.
+///
+/// Each `Request` carries a clone of its server's `Entropy` in
+/// `Request::alloy`, for `Middleware` which need randomness such as
+/// request ids or CSRF tokens:
+///
+/// ```ignore
+/// let id = req.alloy.find::<Entropy>().unwrap().hex_id();
+/// ```
+///
+/// The server uses an `Entropy` seeded by the operating system. Tests
+/// can give it a `seeded` one instead, with `Iron::with_entropy`, so
+/// that generated values are reproducible.
+#[deriving(Clone)]
+pub struct Entropy {
+    rng: Arc<Mutex<StdRng>>
+}
+
+impl Entropy {
+    /// Create a new `Entropy` seeded by the operating system.
+    pub fn new() -> Entropy {
+        let rng = StdRng::new().ok().expect("Failed to seed Entropy from the OS.");
+        Entropy { rng: Arc::new(Mutex::new(rng)) }
+    }
+
+    /// Create a new `Entropy` generating the same values for the same seed.
+    pub fn seeded(seed: &[uint]) -> Entropy {
+        Entropy { rng: Arc::new(Mutex::new(SeedableRng::from_seed(seed))) }
+    }
+
+    /// Fill `dest` with random bytes.
+    pub fn fill_bytes(&self, dest: &mut [u8]) {
+        self.rng.lock().fill_bytes(dest)
+    }
+
+    /// Generate a random `u64`.
+    pub fn gen_u64(&self) -> u64 {
+        self.rng.lock().next_u64()
+    }
+
+    /// Generate 16 random bytes as 32 lowercase hex digits, for use as
+    /// a unique id.
+    pub fn hex_id(&self) -> String {
+        let mut bytes = [0u8, ..16];
+        self.fill_bytes(bytes);
+        bytes.iter().map(|b| format!("{:02x}", *b)).collect::<Vec<String>>().concat()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Entropy;
+
+    #[test]
+    fn seeded_ids_are_reproducible() {
+        let first = Entropy::seeded([1, 2, 3]);
+        let second = Entropy::seeded([1, 2, 3]);
+
+        let ids: Vec<String> = range(0u, 3).map(|_| first.hex_id()).collect();
+        assert_eq!(ids, range(0u, 3).map(|_| second.hex_id()).collect());
+        assert!(ids[0] != ids[1]);
+        assert_eq!(ids[0].len(), 32);
+    }
+
+    #[test]
+    fn clones_share_the_generator() {
+        let entropy = Entropy::seeded([7]);
+        let clone = entropy.clone();
+        let reference = Entropy::seeded([7]);
+
+        let _ = reference.gen_u64();
+        let _ = entropy.gen_u64();
+        assert_eq!(clone.gen_u64(), reference.gen_u64());
+    }
+}
@@ -0,0 +1,189 @@
+//! Exposes the `FirstOf` combinator, which tries a list of `Middleware`
+//! in priority order until one of them handles the request.
+
+use std::fmt::Show;
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue, Unwind, Error};
+
+/// `Middleware` which tries each of its `Middleware` in order until one
+/// returns `Unwind`, and does not enter any of the remaining ones.
+///
+/// Unlike a `StackChain`, where every `Middleware` is entered unless an
+/// earlier one unwinds, `FirstOf` models "try these handlers in order":
+///
+/// ```ignore
+/// server.chain.link(FirstOf::new()
+///                   .or(FromFn::new(serve_cached))
+///                   .or(FromFn::new(serve_fresh)));
+/// ```
+///
+/// `FirstOf` returns `Unwind` when one of its `Middleware` handled the
+/// request and `Continue` if none did. An `Error` stops the search and is
+/// returned to the enclosing `Chain`.
+///
+/// `exit` and `on_error` are only called on the `Middleware` which were
+/// entered, in reverse order. As with a `StackChain`, they are not called
+/// on the one which returned `Unwind` or `Error`. Since the enclosing
+/// `Chain` then does not call them on the `FirstOf` either, the `FirstOf`
+/// calls them on those entered before it itself returns.
+#[deriving(Clone)]
+pub struct FirstOf {
+    middleware: Vec<Box<Middleware + Send>>,
+    entered: uint
+}
+
+impl FirstOf {
+    /// Create a new, empty `FirstOf`.
+    pub fn new() -> FirstOf {
+        FirstOf { middleware: vec![], entered: 0 }
+    }
+
+    /// Add `Middleware` to try after all those added so far.
+    pub fn or<M: Middleware>(mut self, middleware: M) -> FirstOf {
+        self.middleware.push(box middleware as Box<Middleware + Send>);
+        self
+    }
+}
+
+impl Middleware for FirstOf {
+    fn enter(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        self.entered = 0;
+
+        for i in range(0, self.middleware.len()) {
+            self.entered = i + 1;
+            match self.middleware.get_mut(i).enter(req, res) {
+                Continue => (),
+                Unwind => {
+                    for middleware in self.middleware.mut_slice_to(i).mut_iter().rev() {
+                        let _ = middleware.exit(req, res);
+                    }
+                    return Unwind
+                },
+                Error(mut e) => {
+                    for middleware in self.middleware.mut_slice_to(i).mut_iter().rev() {
+                        middleware.on_error(req, res, &mut *e);
+                    }
+                    return Error(e)
+                }
+            }
+        }
+
+        Continue
+    }
+
+    fn exit(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        for middleware in self.middleware.mut_slice_to(self.entered).mut_iter().rev() {
+            let _ = middleware.exit(req, res);
+        }
+        Continue
+    }
+
+    fn on_error(&mut self, req: &mut Request, res: &mut Response, error: &mut Show) {
+        for middleware in self.middleware.mut_slice_to(self.entered).mut_iter().rev() {
+            middleware.on_error(req, res, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt::Show;
+    use std::sync::{Arc, Mutex};
+    use http::method::Get;
+
+    use super::FirstOf;
+    use super::super::mock;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::middleware::{Middleware, Status, Continue, Unwind, Error, FromFn};
+
+    #[deriving(Clone)]
+    struct Counted {
+        entered: Arc<Mutex<u64>>,
+        handles: bool
+    }
+
+    impl Middleware for Counted {
+        fn enter(&mut self, _: &mut Request, _: &mut Response) -> Status {
+            *self.entered.lock() += 1;
+            if self.handles { Unwind } else { Continue }
+        }
+    }
+
+    #[test]
+    fn stops_at_first_handler() {
+        let counts = Vec::from_fn(3, |_| Arc::new(Mutex::new(0u64)));
+        let mut first_of = FirstOf::new()
+            .or(Counted { entered: counts[0].clone(), handles: false })
+            .or(Counted { entered: counts[1].clone(), handles: true })
+            .or(Counted { entered: counts[2].clone(), handles: true });
+
+        match first_of.enter(&mut mock::request(Get, "http://localhost/"),
+                             &mut mock::response()) {
+            Unwind => (),
+            _ => fail!("FirstOf did not unwind when a middleware handled the request.")
+        }
+
+        assert_eq!(*counts[0].lock(), 1);
+        assert_eq!(*counts[1].lock(), 1);
+        assert_eq!(*counts[2].lock(), 0);
+    }
+
+    #[test]
+    fn continues_when_unhandled() {
+        let entered = Arc::new(Mutex::new(0u64));
+        let mut first_of = FirstOf::new()
+            .or(Counted { entered: entered.clone(), handles: false })
+            .or(Counted { entered: entered.clone(), handles: false });
+
+        match first_of.enter(&mut mock::request(Get, "http://localhost/"),
+                             &mut mock::response()) {
+            Continue => (),
+            _ => fail!("FirstOf did not continue when no middleware handled the request.")
+        }
+        assert_eq!(*entered.lock(), 2);
+    }
+
+    // Passes every request on, and records how it is seen out.
+    #[deriving(Clone)]
+    struct Seen { calls: Arc<Mutex<Vec<&'static str>>> }
+
+    impl Middleware for Seen {
+        fn exit(&mut self, _: &mut Request, _: &mut Response) -> Status {
+            self.calls.lock().push("exit");
+            Continue
+        }
+
+        fn on_error(&mut self, _: &mut Request, _: &mut Response, _: &mut Show) {
+            self.calls.lock().push("on_error");
+        }
+    }
+
+    fn handles(_: &mut Request, _: &mut Response) -> Status { Unwind }
+
+    fn fails(_: &mut Request, _: &mut Response) -> Status {
+        Error(box "failed" as Box<Show>)
+    }
+
+    fn seen_out(handler: fn(&mut Request, &mut Response) -> Status) -> Vec<&'static str> {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let mut first_of = FirstOf::new()
+            .or(Seen { calls: calls.clone() })
+            .or(FromFn::new(handler));
+        let _ = first_of.enter(&mut mock::request(Get, "http://localhost/"), &mut mock::response());
+        let calls = calls.lock();
+        calls.clone()
+    }
+
+    #[test]
+    fn exits_entered_middleware_when_handled() {
+        assert_eq!(seen_out(handles), vec!["exit"]);
+    }
+
+    #[test]
+    fn errors_entered_middleware_on_error() {
+        assert_eq!(seen_out(fails), vec!["on_error"]);
+    }
+}
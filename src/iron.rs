@@ -3,17 +3,50 @@
 
 use std::io::net::ip::{SocketAddr, IpAddr};
 use std::cell::RefCell;
+use std::fmt::Show;
+use std::io::{IoResult, IoError, InvalidInput, TimedOut, Listener, Acceptor, Buffer,
+              BufferedStream};
+use std::io::net::tcp::{TcpListener, TcpAcceptor, TcpStream};
+use std::mem;
+use std::cmp;
+use std::io::timer;
 
-use http = http::server;
+use http::status::{Status, InternalServerError, ServiceUnavailable, BadRequest, NotImplemented};
+use http::server::request::{AbsoluteUri, AbsolutePath};
+use http::headers::connection::Token;
 use super::chain::Chain;
 use super::chain::stackchain::StackChain;
+use super::middleware::{Middleware, Continue, Error, ErrorStatus};
+use super::protocol;
+use super::protocol::{Limits, BodyBudget};
+use super::shutdown::Shutdown;
+use super::alloy::Alloy;
+use super::entropy::Entropy;
+use super::logger::Logger;
+use super::access_log::LogContext;
 
-use super::response::{HttpResponse, Response};
+use super::response::Response;
 use super::request::{HttpRequest, Request};
 
 /// The "default server", using a `StackChain`.
 pub type Server = Iron<StackChain>;
 
+/// Renders the response for a request whose `Chain` returned `Error`,
+/// as a status and a body.
+///
+/// It is given the status the request's `ErrorStatus` asks for, or
+/// `500 Internal Server Error` if there is none, and the error.
+pub type ErrorRenderer = fn(Status, &Show) -> (Status, Vec<u8>);
+
+/// Makes final changes to the response to a request, after the `Chain`
+/// has run, including the exits of its `Middleware`, and any error has
+/// been rendered.
+pub type FlushHook = fn(&Request, &mut Response);
+
+/// Told of every error accepting a connection, other than an accept
+/// timing out.
+pub type AcceptErrorHook = fn(IoError);
+
 /// The primary entrance point to `Iron`, a `struct` to instantiate a new server.
 ///
 /// The server can be made with a specific `Chain` (using `from_chain`)
@@ -30,17 +63,135 @@ pub struct Iron<C> {
     /// are passed through those `Middleware`.
     /// `Middleware` is added to the chain with with `chain.link`.
     pub chain: C,
+
+    config: Config
+}
+
+// Server-wide settings, shared by every request.
+struct Config {
+    error_renderer: ErrorRenderer,
+    shutdown: Shutdown,
+    limits: Limits,
+    content_sniffing: bool,
+    close_after_server_error: bool,
+    before_flush: Option<FlushHook>,
+    accept_error_hook: Option<AcceptErrorHook>,
+    fallback: Option<RefCell<StackChain>>,
+    body_budget: Option<BodyBudget>,
+    error_logger: Option<RefCell<Box<Logger + Send>>>,
+    entropy: Entropy,
+    keep_alive_timeout: Option<u64>,
+    read_buffer_size: uint
+}
+
+impl Clone for Config {
+    fn clone(&self) -> Config {
+        Config {
+            error_renderer: self.error_renderer,
+            shutdown: self.shutdown.clone(),
+            limits: self.limits.clone(),
+            content_sniffing: self.content_sniffing,
+            close_after_server_error: self.close_after_server_error,
+            before_flush: self.before_flush,
+            accept_error_hook: self.accept_error_hook,
+            body_budget: self.body_budget.clone(),
+            error_logger: self.error_logger.as_ref().map(|logger| RefCell::new(logger.borrow().clone())),
+            fallback: self.fallback.as_ref().map(|fallback| RefCell::new(fallback.borrow().clone())),
+            entropy: self.entropy.clone(),
+            keep_alive_timeout: self.keep_alive_timeout,
+            read_buffer_size: self.read_buffer_size
+        }
+    }
+}
+
+impl Config {
+    fn new() -> Config {
+        Config {
+            error_renderer: render_error,
+            shutdown: Shutdown::new(),
+            limits: Limits::new(),
+            content_sniffing: false,
+            close_after_server_error: false,
+            before_flush: None,
+            accept_error_hook: None,
+            fallback: None,
+            body_budget: None,
+            error_logger: None,
+            entropy: Entropy::new(),
+            keep_alive_timeout: None,
+            read_buffer_size: BUFFER_BYTES
+        }
+    }
+}
+
+// The default `ErrorRenderer`, which hides the error from the client
+// and only sends the reason phrase of the status.
+fn render_error(status: Status, _: &Show) -> (Status, Vec<u8>) {
+    let body = status.reason().as_slice().as_bytes().to_vec();
+    (status, body)
 }
 
 // The struct which actually listens and serves requests.
 //
-// IronListener holds its chain behind a RefCell to avoid a
-// second clone in the implementation of .serve_forever().
+// IronListener holds its chain behind a RefCell so that requests can be
+// dispatched from `&self`.
+//
+// .accept() clones the IronListener, and so the chain, for each
+// connection and handles the connection's requests in turn, so no two
+// requests are ever dispatched through the same chain at once. The
+// RefCell would fail loudly if that ever changed.
 #[deriving(Clone)]
 struct IronListener<C> {
     chain: RefCell<C>,
-    ip: IpAddr,
-    port: u16
+    config: Config,
+    connection: ConnectionState
+}
+
+// How many bytes of each connection are buffered for writing, and by
+// default for reading. The same as `BufferedStream::new`.
+static BUFFER_BYTES: uint = 64 * 1024;
+
+// How long an accept loop waits for a connection before checking whether
+// the server has been shut down, in milliseconds.
+static ACCEPT_POLL_MS: u64 = 100;
+
+// How long an accept loop waits after an error accepting a connection,
+// at first and at most, in milliseconds. The wait doubles with each
+// error in a row.
+static ACCEPT_BACKOFF_MIN_MS: u64 = 10;
+static ACCEPT_BACKOFF_MAX_MS: u64 = 1000;
+
+// Where an accept loop gets its connections from.
+trait Incoming: Acceptor<TcpStream> {
+    // Make the next `accept` give up with a `TimedOut` error after `ms`
+    // milliseconds.
+    fn wait_at_most(&mut self, ms: u64);
+}
+
+impl Incoming for TcpAcceptor {
+    fn wait_at_most(&mut self, ms: u64) {
+        // The timeout is a deadline, so it is set again for each accept.
+        self.set_timeout(Some(ms));
+    }
+}
+
+// Storage for `Request::connection`.
+//
+// Every connection is served by its own clone of the IronListener,
+// so cloning gives a fresh, empty state which lives exactly as long
+// as the connection.
+struct ConnectionState {
+    alloy: RefCell<Alloy>
+}
+
+impl ConnectionState {
+    fn new() -> ConnectionState {
+        ConnectionState { alloy: RefCell::new(Alloy::new()) }
+    }
+}
+
+impl Clone for ConnectionState {
+    fn clone(&self) -> ConnectionState { ConnectionState::new() }
 }
 
 impl<C: Chain> Iron<C> {
@@ -50,14 +201,69 @@ impl<C: Chain> Iron<C> {
     /// This is a blocking operation, and is the final op that should be called
     /// on the `Iron` instance. Once `listen` is called, requests will be
     /// handled as defined through the `Iron's` `chain's` `Middleware`.
+    ///
+    /// It returns once the `Shutdown` token, see `shutdown`, is signaled,
+    /// or straight away if the address can't be bound.
     pub fn listen(self, ip: IpAddr, port: u16) {
-        use http::server::Server;
+        let addr = SocketAddr { ip: ip, port: port };
+        match bind(addr) {
+            Ok((acceptor, _)) => self.into_listener().accept(acceptor),
+            Err(e) => error!("Error listening on {}: {}", addr, e)
+        }
+    }
+
+    /// Kick off the server process on an address given as a string.
+    ///
+    /// The address is `host:port`, with IPv6 hosts in brackets, as in
+    /// `127.0.0.1:3000` or `[::1]:3000`. Hostnames are not resolved.
+    /// An invalid address is returned as an `InvalidInput` error before
+    /// anything is bound; otherwise this blocks like `listen`.
+    pub fn listen_str(self, addr: &str) -> IoResult<()> {
+        let addr = try!(parse_addr(addr));
+        self.listen(addr.ip, addr.port);
+        Ok(())
+    }
+
+    /// Kick off a server process listening on each of `addrs`.
+    ///
+    /// Unlike `listen`, this does not block: each address gets its own
+    /// task accepting connections, and all of them share this `Iron's`
+    /// `chain` and settings. The returned `Shutdown` token is shared by
+    /// the requests to every address, and signaling it stops every accept
+    /// loop. Addresses which can't be bound are logged and skipped.
+    pub fn listen_all(self, addrs: &[SocketAddr]) -> Shutdown {
+        let shutdown = self.shutdown();
+        let _ = self.spawn_listeners(addrs);
+        shutdown
+    }
+
+    // Bind each of `addrs` and accept connections on it in a task of its
+    // own, giving the addresses bound. Those which can't be bound are
+    // logged and left out.
+    fn spawn_listeners(self, addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+        let listener = self.into_listener();
+        let mut bound = vec![];
+
+        for addr in addrs.iter() {
+            match bind(*addr) {
+                Ok((acceptor, local)) => {
+                    let listener = listener.clone();
+                    spawn(proc() { listener.accept(acceptor) });
+                    bound.push(local);
+                },
+                Err(e) => error!("Error listening on {}: {}", addr, e)
+            }
+        }
 
+        bound
+    }
+
+    fn into_listener(self) -> IronListener<C> {
         IronListener {
             chain: RefCell::new(self.chain),
-            ip: ip,
-            port: port
-        }.serve_forever();
+            config: self.config,
+            connection: ConnectionState::new()
+        }
     }
 
     /// Instantiate a new instance of `Iron`.
@@ -70,29 +276,1273 @@ impl<C: Chain> Iron<C> {
     pub fn new() -> Iron<C> {
         Iron {
             chain: Chain::new(),
+            config: Config::new()
+        }
+    }
+
+    /// Set how the response is rendered when the `chain` returns `Error`.
+    ///
+    /// The renderer is only used if no `Middleware` set a status from
+    /// its `on_error` handler. The default serves the status asked for
+    /// by the request's `ErrorStatus`, `500 Internal Server Error` if
+    /// none, with its reason phrase as the body.
+    pub fn with_error_renderer(mut self, renderer: ErrorRenderer) -> Iron<C> {
+        self.config.error_renderer = renderer;
+        self
+    }
+
+    /// Set the maximum length of a request URI, in bytes.
+    ///
+    /// Requests with a longer URI are answered with `414 URI Too Long`,
+    /// and the connection is closed. Defaults to 8 KiB.
+    pub fn with_max_uri_bytes(mut self, max: uint) -> Iron<C> {
+        self.config.limits.max_uri_bytes = max;
+        self
+    }
+
+    /// Set the maximum number of distinct headers in a request.
+    ///
+    /// Requests with more are answered with `431 Request Header Fields Too
+    /// Large`, and the connection is closed. Defaults to 100.
+    ///
+    /// A header repeated on many lines counts once: the request has been
+    /// parsed, and its repeated headers folded together, by the time this
+    /// is checked. It limits what middleware has to look through, not the
+    /// work of parsing.
+    pub fn with_max_header_count(mut self, max: uint) -> Iron<C> {
+        self.config.limits.max_header_count = max;
+        self
+    }
+
+    /// Limit how many requests are handled at once by the size of their
+    /// bodies: at most `bytes` of request bodies, summed across all
+    /// connections, may be in the hands of the `chain` at the same time.
+    ///
+    /// A request whose body doesn't fit in what remains of the limit is
+    /// answered with `503 Service Unavailable`, and the connection closed.
+    /// Its part of the limit is given back once its response has been
+    /// written. This is not a bound on memory: rust-http has read the
+    /// whole body by the time it is checked, so a rejected body has been
+    /// received all the same. There is no limit by default.
+    pub fn with_max_handled_body_bytes(mut self, bytes: uint) -> Iron<C> {
+        self.config.body_budget = Some(BodyBudget::new(bytes));
+        self
+    }
+
+    /// Set whether the Content-Type of responses which have none is guessed
+    /// from the first bytes of their body.
+    ///
+    /// This is off by default, in which case such responses are sent
+    /// as `text/plain`.
+    pub fn with_content_sniffing(mut self, sniff: bool) -> Iron<C> {
+        self.config.content_sniffing = sniff;
+        self
+    }
+
+    /// Set whether connections are closed after a `5xx` response.
+    ///
+    /// A server error may leave whatever state the connection holds in
+    /// doubt, so with this on such responses are sent with `Connection:
+    /// close` and the connection is not reused. This is off by default.
+    pub fn with_close_after_server_error(mut self, close: bool) -> Iron<C> {
+        self.config.close_after_server_error = close;
+        self
+    }
+
+    /// Log every `Error` returned by the `chain` to `logger`.
+    ///
+    /// Each error is logged as a logfmt line with the method and path of
+    /// the request, along with any fields other `Middleware` added to its
+    /// `LogContext`, such as a request id, and then the error itself. By
+    /// default the same line is logged with `error!`.
+    pub fn with_error_logger(mut self, logger: Box<Logger + Send>) -> Iron<C> {
+        self.config.error_logger = Some(RefCell::new(logger));
+        self
+    }
+
+    /// Pass requests which the `chain` leaves unhandled to `fallback`,
+    /// rather than answering them with a `404 Not Found`.
+    ///
+    /// A request is unhandled when the `chain` returns `Continue` without
+    /// setting a status. The `fallback` is then dispatched like a `chain`
+    /// of its own, so it can be another application being composed with
+    /// this one. Requests it leaves unhandled still get the 404.
+    pub fn with_fallback<M: Middleware>(mut self, fallback: M) -> Iron<C> {
+        let mut chain: StackChain = Chain::new();
+        chain.link(fallback);
+        self.config.fallback = Some(RefCell::new(chain));
+        self
+    }
+
+    /// Set a hook called on every response just before it is written.
+    ///
+    /// The hook runs after the `Chain` is done with the request, so it
+    /// sees responses to requests which ended in an `Error` as well, once
+    /// the `ErrorRenderer` has made them. This makes it the place for
+    /// final touches, such as signing the response.
+    pub fn with_before_flush(mut self, hook: FlushHook) -> Iron<C> {
+        self.config.before_flush = Some(hook);
+        self
+    }
+
+    /// Call `hook` with every error accepting a connection, such as the
+    /// process running out of file descriptors, instead of logging it
+    /// with `error!`.
+    ///
+    /// Either way the server keeps accepting connections, but it waits
+    /// a little after each error, longer for each in a row, so that one
+    /// which persists doesn't keep it busy.
+    pub fn on_accept_error(mut self, hook: AcceptErrorHook) -> Iron<C> {
+        self.config.accept_error_hook = Some(hook);
+        self
+    }
+
+    /// Set the `Entropy` shared with every request to this server.
+    ///
+    /// By default it is seeded by the operating system; pass an
+    /// `Entropy::seeded` one for reproducible tests.
+    pub fn with_entropy(mut self, entropy: Entropy) -> Iron<C> {
+        self.config.entropy = entropy;
+        self
+    }
+
+    /// Close connections which sit idle for `ms` milliseconds waiting
+    /// for their next request, or their first.
+    ///
+    /// Only the wait for a request to begin is timed: once its first
+    /// bytes have arrived, reading the rest of it and handling it take
+    /// as long as they take. Idle connections are kept open by default.
+    pub fn with_keep_alive_timeout(mut self, ms: u64) -> Iron<C> {
+        self.config.keep_alive_timeout = Some(ms);
+        self
+    }
+
+    /// Set how many bytes of a connection are read into its buffer at
+    /// most, by each read from the socket, as requests are parsed.
+    ///
+    /// A bigger buffer takes fewer reads for requests with large headers,
+    /// at the cost of the memory held for every connection. It is no
+    /// limit on the size of requests. Defaults to 64 KiB, and it is at
+    /// least one byte.
+    pub fn with_read_buffer_size(mut self, bytes: uint) -> Iron<C> {
+        self.config.read_buffer_size = cmp::max(bytes, 1);
+        self
+    }
+
+    /// Get the `Shutdown` token shared with every request to this server.
+    ///
+    /// Signal it to ask long-running `Middleware` to finish.
+    pub fn shutdown(&self) -> Shutdown {
+        self.config.shutdown.clone()
+    }
+}
+
+// Bind a listening socket to `addr`, giving it along with the address it
+// is bound to, whose port is picked by the system if `addr` asks for 0.
+fn bind(addr: SocketAddr) -> IoResult<(TcpAcceptor, SocketAddr)> {
+    let mut listener = try!(TcpListener::bind(addr.ip.to_string().as_slice(), addr.port));
+    let bound = try!(listener.socket_name());
+    let acceptor = try!(listener.listen());
+    Ok((acceptor, bound))
+}
+
+// Parse `addr`, such as "127.0.0.1:3000" or "[::1]:3000", giving an
+// `InvalidInput` error for anything else.
+fn parse_addr(addr: &str) -> IoResult<SocketAddr> {
+    match from_str::<SocketAddr>(addr.trim()) {
+        Some(addr) => Ok(addr),
+        None => Err(IoError {
+            kind: InvalidInput,
+            desc: "invalid socket address",
+            detail: Some(addr.to_string())
+        })
+    }
+}
+
+// Run a request through `chain` and deal with the outcome.
+fn dispatch<C: Chain>(chain: &mut C, config: &Config, req: &mut Request, res: &mut Response) {
+    let status = match chain.dispatch(req, res) {
+        Continue if res.status.is_none() => match config.fallback {
+            Some(ref fallback) => {
+                // An `ErrorStatus` left by the `chain`, which did not
+                // error, is not meant for the errors of the `fallback`.
+                req.alloy.remove::<ErrorStatus>();
+                fallback.borrow_mut().dispatch(req, res)
+            },
+            None => Continue
+        },
+        status => status
+    };
+
+    match status {
+        Error(ref e) => log_error(config, req, &**e),
+        _ => ()
+    }
+
+    match status {
+        Error(ref e) if res.status.is_none() => {
+            let intended = match req.alloy.find::<ErrorStatus>() {
+                Some(&ErrorStatus(ref status)) => status.clone(),
+                None => InternalServerError
+            };
+            let (status, body) = (config.error_renderer)(intended, &**e);
+            res.serve(status, body);
+        },
+        _ => ()
+    }
+
+    // The `ErrorStatus` belongs to the `Error` it was returned with, and
+    // must not outlive it in `alloy`.
+    req.alloy.remove::<ErrorStatus>();
+
+    match config.before_flush {
+        Some(hook) => hook(req, res),
+        None => ()
+    }
+}
+
+// Log an error from the chain with what is known about its request.
+fn log_error(config: &Config, req: &Request, error: &Show) {
+    let mut context = match req.alloy.find::<LogContext>() {
+        Some(context) => context.clone(),
+        None => {
+            let mut context = LogContext::new();
+            context.add_field("method", req.method.to_string().as_slice());
+            context.add_field("path", req.url.serialize_path().unwrap_or(String::new()).as_slice());
+            context
         }
+    };
+    context.add_field("error", format!("{}", error).as_slice());
+
+    match config.error_logger {
+        Some(ref logger) => logger.borrow_mut().log(context.to_logfmt().as_slice()),
+        None => error!("{}", context.to_logfmt())
+    }
+}
+
+impl<C: Chain> IronListener<C> {
+    // Dispatch a request received on this listener's connection.
+    fn serve(&self, req: &mut Request, res: &mut Response) {
+        req.alloy.insert::<Shutdown>(self.config.shutdown.clone());
+        req.alloy.insert::<Entropy>(self.config.entropy.clone());
+
+        // Lend the connection's state to the request while it is handled.
+        let mut connection = self.connection.alloy.borrow_mut();
+        mem::swap(&mut req.connection, &mut *connection);
+        dispatch(&mut *self.chain.borrow_mut(), &self.config, req, res);
+        mem::swap(&mut req.connection, &mut *connection);
     }
 }
 
-impl<C: Chain> http::Server for IronListener<C> {
-    fn get_config(&self) -> http::Config {
-        http::Config {
-            bind_address: SocketAddr {
-                ip: self.ip,
-                port: self.port
+impl<C: Chain> IronListener<C> {
+    // Accept connections on `acceptor` until the server's `Shutdown` token
+    // is signaled, serving each in a task of its own with a clone of this
+    // listener.
+    fn accept<A: Incoming>(self, mut acceptor: A) {
+        let mut backoff = ACCEPT_BACKOFF_MIN_MS;
+
+        while !self.config.shutdown.is_signaled() {
+            acceptor.wait_at_most(ACCEPT_POLL_MS);
+            match acceptor.accept() {
+                Ok(stream) => {
+                    backoff = ACCEPT_BACKOFF_MIN_MS;
+                    let listener = self.clone();
+                    spawn(proc() { listener.serve_connection(stream) });
+                },
+                Err(ref e) if e.kind == TimedOut => (),
+                Err(e) => {
+                    match self.config.accept_error_hook {
+                        Some(hook) => hook(e),
+                        None => error!("Error accepting connection: {}", e)
+                    }
+                    timer::sleep(backoff);
+                    backoff = cmp::min(backoff * 2, ACCEPT_BACKOFF_MAX_MS);
+                }
             }
         }
     }
 
-    fn handle_request(&self, http_req: HttpRequest, http_res: &mut HttpResponse) {
+    // Answer the requests on `stream` in turn, and close it once one of
+    // them, or its response, asks for that.
+    //
+    // Each request on the connection, body included, is only read once
+    // the previous one has been handled and its response written, so
+    // pipelined requests are served one at a time and in order. The parser
+    // reads bodies framed by Content-Length whole before the chain runs,
+    // so a handler which ignores one can't leave unread bytes to desync
+    // the next request. Bodies sent with Transfer-Encoding, which it
+    // leaves on the socket, are refused by `protocol::check`, and the
+    // connection closed.
+    fn serve_connection(&self, stream: TcpStream) {
+        let mut stream = stream;
+        let local_addr = match stream.socket_name() {
+            Ok(addr) => addr,
+            Err(e) => { error!("Error reading local address: {}", e); return }
+        };
+        let mut stream = BufferedStream::with_capacities(self.config.read_buffer_size,
+                                                         BUFFER_BYTES, stream);
+
+        loop {
+            // Wait for the next request to begin, for no longer than the
+            // keep-alive timeout. A pipelined request already in the
+            // buffer is there straight away. If the client hangs up, or
+            // the wait times out, there is nothing to answer.
+            stream.get_mut_ref().set_read_timeout(self.config.keep_alive_timeout);
+            match stream.fill_buf() {
+                Ok(_) => (),
+                Err(ref e) if e.kind == TimedOut => { debug!("Closing idle connection"); return },
+                Err(_) => return
+            }
+            stream.get_mut_ref().set_read_timeout(None);
+
+            let (http_req, parsed) = HttpRequest::load(&mut stream);
+            let keep_alive = match parsed {
+                Ok(()) => self.handle(*http_req, local_addr, &mut stream),
+                Err(status) => { reject(status, &mut stream); false }
+            };
+
+            match stream.flush() {
+                Ok(()) => (),
+                Err(e) => { debug!("Error flushing response: {}", e); return }
+            }
+
+            // Dropping the stream closes the connection.
+            if !keep_alive { return }
+        }
+    }
+
+    // Check, dispatch and answer a request on the connection `w`, giving
+    // whether the connection can be kept alive.
+    fn handle<W: Writer>(&self, http_req: HttpRequest, local_addr: SocketAddr, w: &mut W) -> bool {
+        // Reject unsafe requests before they reach the chain
+        match protocol::check(&http_req, &self.config.limits) {
+            Ok(()) => (),
+            Err(error) => { reject(error.status(), w); return false }
+        }
+
+        // Held until the response has been written.
+        let _reservation = match self.config.body_budget {
+            Some(ref budget) => match budget.reserve(http_req.body.len()) {
+                Some(reservation) => Some(reservation),
+                None => { reject(ServiceUnavailable, w); return false }
+            },
+            None => None
+        };
+
         // Create wrapper Request and Response
-        let mut req = Request::from_http(http_req).unwrap();
-        let mut res = Response::from_http(http_res);
+        let version = http_req.version;
+        let mut res = Response::from_http(&http_req);
+        // Only requests for a path or a URI are served. `OPTIONS *` and
+        // `CONNECT` are well-formed, but not implemented, and a request
+        // for a path without a Host header can't be given a URL.
+        let targets_resource = match http_req.request_uri {
+            AbsoluteUri(_) | AbsolutePath(_) => true,
+            _ => false
+        };
+        let mut req = match Request::from_http(http_req, local_addr) {
+            Some(req) => req,
+            None if targets_resource => { reject(BadRequest, w); return false },
+            None => { reject(NotImplemented, w); return false }
+        };
 
         // Dispatch the request
-        let _ = self.chain.borrow_mut().dispatch(&mut req, &mut res);
+        self.serve(&mut req, &mut res);
+        connection_headers(version, &self.config, &mut res);
+        let keep_alive = res.will_keep_alive();
+
+        // A `ResponseStream` left in the alloy would keep the response from
+        // ever ending.
+        req.alloy = Alloy::new();
+
+        // Write the response back to the client
+        match res.write_back(&req, w, self.config.content_sniffing) {
+            Ok(()) => keep_alive,
+            Err(e) => { error!("Error writing response: {}", e); false }
+        }
+    }
+}
+
+// Tell the client whether its connection is kept alive, where it
+// wouldn't assume so: HTTP/1.0 clients expect it to be closed unless
+// told `Connection: keep-alive`, and either kind is told when it is
+// closed after all, as after a 5xx if `close_after_server_error` is set.
+fn connection_headers(version: (uint, uint), config: &Config, res: &mut Response) {
+    let server_error = match res.status {
+        Some(ref status) => status.code() >= 500,
+        None => false
+    };
+
+    if config.close_after_server_error && server_error {
+        res.set_connection_close();
+    }
+
+    if !res.will_keep_alive() {
+        res.set_connection_close();
+    } else if version == (1, 0) {
+        res.headers.connection = Some(vec![Token("keep-alive".to_string())]);
+    }
+}
+
+// Answer a request which failed `protocol::check`, with no body, and
+// tell the client that the connection is closed.
+fn reject<W: Writer>(status: Status, w: &mut W) {
+    let _ = write!(w, "HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                   status.code(), status.reason())
+        .map_err(|e| error!("Error writing rejection: {}", e));
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt::Show;
+    use http::method::Get;
+    use http::status::{BadRequest, NotFound, InternalServerError};
+    use HttpStatus = http::status::Status;
+
+    use std::cell::RefCell;
+    use std::sync::{Arc, Mutex};
+    use std::io::net::ip::{SocketAddr, Ipv4Addr, Ipv6Addr};
+    use std::io::{IoResult, IoError, InvalidInput, OtherIoError, standard_error, Acceptor};
+    use std::io::net::tcp::TcpAcceptor;
+
+    use http::headers::connection::{Close, Token};
+
+    use std::io::TcpStream;
+
+    use super::{Iron, Server, dispatch, parse_addr, connection_headers, Config, IronListener,
+                ConnectionState, Incoming, bind};
+    use super::super::shutdown::Shutdown;
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::{Response, STREAM_BUFFER};
+    use super::super::middleware::{Middleware, Status, Continue, Unwind, Error, ErrorStatus,
+                                   FromFn};
+
+    fn fails(_: &mut Request, _: &mut Response) -> Status {
+        Error(box "no such user" as Box<Show>)
+    }
+
+    fn fails_with_bad_request(req: &mut Request, res: &mut Response) -> Status {
+        req.alloy.insert(ErrorStatus(BadRequest));
+        fails(req, res)
+    }
+
+    fn fails_with_server_error(req: &mut Request, res: &mut Response) -> Status {
+        req.alloy.insert(ErrorStatus(InternalServerError));
+        fails(req, res)
+    }
+
+    fn render_json(_: HttpStatus, error: &Show) -> (HttpStatus, Vec<u8>) {
+        (NotFound, format!("{{\"error\":\"{}\"}}", error).into_bytes())
+    }
+
+    fn errored_with(failing: fn(&mut Request, &mut Response) -> Status, config: &Config) -> Response {
+        let mut chain: StackChain = Chain::new();
+        chain.link(FromFn::new(failing));
+
+        let mut res = mock::response();
+        dispatch(&mut chain, config, &mut mock::request(Get, "http://localhost/"), &mut res);
+        res
+    }
+
+    fn errored_response(config: &Config) -> Response {
+        errored_with(fails, config)
+    }
+
+    #[test]
+    fn parses_ipv4_addr() {
+        assert_eq!(parse_addr("127.0.0.1:3000"),
+                   Ok(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 3000 }));
+    }
+
+    #[test]
+    fn parses_bracketed_ipv6_addr() {
+        assert_eq!(parse_addr("[::1]:3000"),
+                   Ok(SocketAddr { ip: Ipv6Addr(0, 0, 0, 0, 0, 0, 0, 1), port: 3000 }));
+    }
+
+    #[test]
+    fn rejects_malformed_addr() {
+        for addr in ["", "127.0.0.1", "127.0.0.1:", ":3000", "::1:3000",
+                     "127.0.0.1:99999", "localhost:3000"].iter() {
+            assert_eq!(parse_addr(*addr).unwrap_err().kind, InvalidInput);
+        }
+    }
+
+    fn sign(_: &Request, res: &mut Response) {
+        res.set_header("X-Signature", "signed");
+    }
+
+    fn signature(res: &Response) -> Option<String> {
+        res.headers.extensions.find_equiv(&"X-Signature").map(|value| value.clone())
+    }
+
+    #[test]
+    fn runs_before_flush_hook_on_every_response() {
+        let mut config = Config::new();
+        config.before_flush = Some(sign);
+
+        let mut chain: StackChain = Chain::new();
+        chain.link(FromFn::new(hello));
+        let mut res = mock::response();
+        dispatch(&mut chain, &config, &mut mock::request(Get, "http://localhost/"), &mut res);
+        assert_eq!(signature(&res), Some("signed".to_string()));
+
+        let res = errored_response(&config);
+        assert_eq!(res.status, Some(InternalServerError));
+        assert_eq!(signature(&res), Some("signed".to_string()));
+    }
+
+    fn passes(_: &mut Request, _: &mut Response) -> Status {
+        Continue
+    }
+
+    fn fallback(_: &mut Request, res: &mut Response) -> Status {
+        res.serve(::http::status::Ok, "Fallback");
+        Unwind
+    }
+
+    #[test]
+    fn passes_unhandled_requests_to_fallback() {
+        let mut config = Config::new();
+        let mut fallback_chain: StackChain = Chain::new();
+        fallback_chain.link(FromFn::new(fallback));
+        config.fallback = Some(RefCell::new(fallback_chain));
+
+        let mut chain: StackChain = Chain::new();
+        chain.link(FromFn::new(passes));
+        let mut res = mock::response();
+        dispatch(&mut chain, &config, &mut mock::request(Get, "http://localhost/"), &mut res);
+        assert_eq!(res.body.read_to_end().unwrap(), b"Fallback".to_vec());
+
+        let mut chain: StackChain = Chain::new();
+        chain.link(FromFn::new(hello));
+        let mut res = mock::response();
+        dispatch(&mut chain, &config, &mut mock::request(Get, "http://localhost/"), &mut res);
+        assert_eq!(res.body.read_to_end().unwrap(), b"Hello".to_vec());
+    }
+
+    fn sets_status_then_passes(req: &mut Request, _: &mut Response) -> Status {
+        req.alloy.insert(ErrorStatus(BadRequest));
+        Continue
+    }
+
+    #[test]
+    fn does_not_keep_error_status_for_fallback() {
+        let mut config = Config::new();
+        let mut fallback_chain: StackChain = Chain::new();
+        fallback_chain.link(FromFn::new(fails));
+        config.fallback = Some(RefCell::new(fallback_chain));
+
+        let mut chain: StackChain = Chain::new();
+        chain.link(FromFn::new(sets_status_then_passes));
+        let mut req = mock::request(Get, "http://localhost/");
+        let mut res = mock::response();
+        dispatch(&mut chain, &config, &mut req, &mut res);
+        assert_eq!(res.status, Some(InternalServerError));
+        assert!(req.alloy.find::<ErrorStatus>().is_none());
+    }
+
+    #[test]
+    fn logs_errors_with_request_context() {
+        let logger = mock::CaptureLogger::new();
+        let mut config = Config::new();
+        config.error_logger = Some(RefCell::new(logger.boxed()));
+
+        let mut chain: StackChain = Chain::new();
+        chain.link(FromFn::new(fails));
+        dispatch(&mut chain, &config, &mut mock::request(Get, "http://localhost/users/7"),
+                 &mut mock::response());
+
+        assert_eq!(logger.lines(), vec!["method=GET path=/users/7 error=\"no such user\"".to_string()]);
+    }
+
+    #[test]
+    fn renders_default_error() {
+        let mut res = errored_response(&Config::new());
+        assert_eq!(res.status, Some(InternalServerError));
+        assert_eq!(res.body.read_to_end().unwrap(), b"Internal Server Error".to_vec());
+    }
+
+    #[test]
+    fn renders_custom_error() {
+        let mut config = Config::new();
+        config.error_renderer = render_json;
+
+        let mut res = errored_response(&config);
+        assert_eq!(res.status, Some(NotFound));
+        assert_eq!(res.body.read_to_end().unwrap(),
+                   b"{\"error\":\"no such user\"}".to_vec());
+    }
+
+    #[test]
+    fn renders_client_error_status() {
+        let mut res = errored_with(fails_with_bad_request, &Config::new());
+        assert_eq!(res.status, Some(BadRequest));
+        assert_eq!(res.body.read_to_end().unwrap(), b"Bad Request".to_vec());
+    }
+
+    #[test]
+    fn renders_server_error_status() {
+        let mut res = errored_with(fails_with_server_error, &Config::new());
+        assert_eq!(res.status, Some(InternalServerError));
+        assert_eq!(res.body.read_to_end().unwrap(), b"Internal Server Error".to_vec());
+    }
+
+    #[test]
+    fn closes_after_server_error_when_enabled() {
+        let mut config = Config::new();
+        let mut res = errored_with(fails_with_server_error, &config);
+        connection_headers((1, 1), &config, &mut res);
+        assert_eq!(res.headers.connection, None);
+
+        config.close_after_server_error = true;
+        let mut res = errored_with(fails_with_server_error, &config);
+        connection_headers((1, 1), &config, &mut res);
+        assert_eq!(res.headers.connection, Some(vec![Close]));
+
+        let mut res = errored_with(fails_with_bad_request, &config);
+        connection_headers((1, 1), &config, &mut res);
+        assert_eq!(res.headers.connection, None);
+    }
+
+    #[test]
+    fn closes_socket_after_server_error_when_enabled() {
+        let mut server: Server = Iron::new().with_close_after_server_error(true);
+        server.chain.link(FromFn::new(fails));
+        let (port, shutdown) = start(server);
+
+        let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+        stream.set_read_timeout(Some(5000));
+        stream.write(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        // Only returns once the server has closed the connection.
+        let wire = String::from_utf8(stream.read_to_end().unwrap()).unwrap();
+        assert!(wire.as_slice().starts_with("HTTP/1.1 500"));
+        assert!(wire.as_slice().contains("Connection: close\r\n"));
+        shutdown.signal();
+    }
+
+    #[test]
+    fn closes_socket_after_rejecting_request() {
+        let mut server: Server = Iron::new().with_max_uri_bytes(16);
+        server.chain.link(FromFn::new(hello));
+        let (port, shutdown) = start(server);
+
+        let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+        stream.set_read_timeout(Some(5000));
+        stream.write(b"GET /0123456789abcdef HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        // Only returns once the server has closed the connection.
+        let wire = String::from_utf8(stream.read_to_end().unwrap()).unwrap();
+        assert!(wire.as_slice().starts_with("HTTP/1.1 414"));
+        assert!(wire.as_slice().contains("Connection: close\r\n"));
+        shutdown.signal();
+    }
+
+    #[test]
+    fn counts_repeated_header_once() {
+        let mut server: Server = Iron::new().with_max_header_count(3);
+        server.chain.link(FromFn::new(hello));
+        let (port, shutdown) = start(server);
+
+        let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+        stream.set_read_timeout(Some(5000));
+        let same = "X-Same: x\r\n".repeat(10);
+        stream.write(format!("GET / HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n",
+                             same).as_bytes()).unwrap();
+
+        let wire = String::from_utf8(stream.read_to_end().unwrap()).unwrap();
+        assert!(wire.as_slice().starts_with("HTTP/1.1 200"));
+        shutdown.signal();
+    }
+
+    #[test]
+    fn answers_options_star() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(hello));
+        let (port, shutdown) = start(server);
+
+        let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+        stream.set_read_timeout(Some(5000));
+        stream.write(b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        let wire = String::from_utf8(stream.read_to_end().unwrap()).unwrap();
+        assert!(wire.as_slice().starts_with("HTTP/1.1 501"));
+        shutdown.signal();
+    }
+
+    #[test]
+    fn rejects_http_1_0_request_without_host() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(hello));
+        let (port, shutdown) = start(server);
+
+        let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+        stream.set_read_timeout(Some(5000));
+        stream.write(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+
+        let wire = String::from_utf8(stream.read_to_end().unwrap()).unwrap();
+        assert!(wire.as_slice().starts_with("HTTP/1.1 400"));
+        shutdown.signal();
+    }
+
+    #[test]
+    fn echoes_http_1_0_keep_alive() {
+        let mut res = mock::response();
+        connection_headers((1, 0), &Config::new(), &mut res);
+        assert_eq!(res.headers.connection, Some(vec![Token("keep-alive".to_string())]));
+
+        let mut res = mock::response();
+        connection_headers((1, 1), &Config::new(), &mut res);
+        assert_eq!(res.headers.connection, None);
+    }
+
+    // Count the requests made on the connection.
+    fn count(req: &mut Request, res: &mut Response) -> Status {
+        let count = match req.connection.find_mut::<uint>() {
+            Some(count) => { *count += 1; *count },
+            None => 1
+        };
+        if count == 1 { req.connection.insert::<uint>(1); }
+
+        res.serve(::http::status::Ok, count.to_string());
+        Unwind
+    }
+
+    fn served_count(listener: &IronListener<StackChain>) -> Vec<u8> {
+        let mut res = mock::response();
+        listener.serve(&mut mock::request(Get, "http://localhost/"), &mut res);
+        res.body.read_to_end().unwrap()
+    }
+
+    #[test]
+    fn connection_state_persists_across_requests() {
+        let mut chain: StackChain = Chain::new();
+        chain.link(FromFn::new(count));
+        let listener = IronListener {
+            chain: RefCell::new(chain),
+            config: Config::new(),
+            connection: ConnectionState::new()
+        };
+
+        assert_eq!(served_count(&listener), b"1".to_vec());
+        assert_eq!(served_count(&listener), b"2".to_vec());
+
+        // A new connection is served by a new clone.
+        assert_eq!(served_count(&listener.clone()), b"1".to_vec());
+    }
+
+    fn hello(_: &mut Request, res: &mut Response) -> Status {
+        res.serve(::http::status::Ok, "Hello");
+        Unwind
+    }
+
+    // Serve `server` on a port of the loopback interface picked by the
+    // system, giving the port and the token which stops the server.
+    fn start(server: Server) -> (u16, Shutdown) {
+        let shutdown = server.shutdown();
+        let bound = server.spawn_listeners([SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 }]);
+        (bound[0].port, shutdown)
+    }
+
+    fn says_bye(_: &mut Request, res: &mut Response) -> Status {
+        res.set_connection_close();
+        res.serve(::http::status::Ok, "Bye");
+        Unwind
+    }
+
+    #[test]
+    fn closes_connection_when_asked() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(says_bye));
+        let (port, shutdown) = start(server);
+
+        // An HTTP/1.1 request, whose connection would be kept alive.
+        let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+        stream.set_read_timeout(Some(5000));
+        stream.write(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        // Only returns once the server has closed the connection.
+        let wire = String::from_utf8(stream.read_to_end().unwrap()).unwrap();
+        assert!(wire.as_slice().contains("Connection: close\r\n"));
+        assert!(wire.as_slice().ends_with("Bye"));
+        shutdown.signal();
+    }
+
+    fn echo_local_port(req: &mut Request, res: &mut Response) -> Status {
+        res.serve(::http::status::Ok, format!("[{}]", req.local_addr.port));
+        Unwind
+    }
+
+    #[test]
+    fn gives_local_addr_of_connection() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(echo_local_port));
+        let (port, shutdown) = start(server);
+
+        assert!(get(port).as_slice().ends_with(format!("[{}]", port).as_slice()));
+        shutdown.signal();
+    }
+
+    fn tells_keep_alive(_: &mut Request, res: &mut Response) -> Status {
+        let keep_alive = res.will_keep_alive();
+        res.serve(::http::status::Ok, format!("[{}]", keep_alive));
+        Unwind
+    }
+
+    // Send `head`, the request line and headers of a request, to a server
+    // answering with `tells_keep_alive`, and give the response.
+    fn keep_alive_answer(head: &str) -> String {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(tells_keep_alive));
+        let (port, shutdown) = start(server);
+
+        let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+        stream.set_read_timeout(Some(5000));
+        stream.write(format!("{}\r\n\r\n", head).as_bytes()).unwrap();
+
+        // Read up to the end of the body, as the connection may be kept.
+        let mut wire = String::new();
+        while !wire.as_slice().ends_with("]") {
+            wire.push_char(stream.read_byte().unwrap() as char);
+        }
+        shutdown.signal();
+        wire
+    }
+
+    #[test]
+    fn keeps_http_1_0_alive_only_when_asked() {
+        let kept = keep_alive_answer("GET / HTTP/1.0\r\nHost: localhost\r\nConnection: keep-alive");
+        assert!(kept.as_slice().contains("Connection: keep-alive\r\n"));
+        assert!(kept.as_slice().ends_with("[true]"));
+
+        let closed = keep_alive_answer("GET / HTTP/1.0\r\nHost: localhost");
+        assert!(closed.as_slice().contains("Connection: close\r\n"));
+        assert!(closed.as_slice().ends_with("[false]"));
+    }
+
+    #[test]
+    fn tells_middleware_whether_connection_is_kept() {
+        let kept = keep_alive_answer("GET / HTTP/1.1\r\nHost: localhost");
+        assert!(kept.as_slice().ends_with("[true]"));
+
+        let closed = keep_alive_answer("GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close");
+        assert!(closed.as_slice().ends_with("[false]"));
+    }
+
+    fn sets_cookies(_: &mut Request, res: &mut Response) -> Status {
+        res.set_cookie("theme=dark");
+        res.set_cookie("lang=is");
+        res.serve(::http::status::Ok, "Hello");
+        Unwind
+    }
+
+    #[test]
+    fn sends_each_cookie_in_a_header_of_its_own() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(sets_cookies));
+        let (port, shutdown) = start(server);
+
+        let wire = get(port);
+        let cookies: Vec<&str> = wire.as_slice().split_str("\r\n")
+            .filter(|line| line.starts_with("Set-Cookie: ")).collect();
+        assert_eq!(cookies, vec!["Set-Cookie: theme=dark", "Set-Cookie: lang=is"]);
+        shutdown.signal();
+    }
+
+    fn streams_with_trailer(_: &mut Request, res: &mut Response) -> Status {
+        res.set_trailer("X-Checksum", "abc").unwrap();
+        let mut stream = res.stream();
+        let _ = stream.write(b"Hello").and_then(|_| stream.flush());
+        Unwind
+    }
+
+    #[test]
+    fn sends_trailers_after_streamed_body() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(streams_with_trailer));
+        let (port, shutdown) = start(server);
+
+        let mut client = TcpStream::connect("127.0.0.1", port).unwrap();
+        client.write(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let wire = String::from_utf8(client.read_to_end().unwrap()).unwrap();
+        assert!(wire.as_slice().contains("\r\nTrailer: X-Checksum\r\n"));
+        assert!(wire.as_slice().ends_with("5\r\nHello\r\n0\r\nX-Checksum: abc\r\n\r\n"));
+        shutdown.signal();
+    }
+
+    // Streams "first", then "second" once told to.
+    #[deriving(Clone)]
+    struct StreamsWhenTold { told: Arc<Mutex<Option<Receiver<()>>>> }
+
+    impl Middleware for StreamsWhenTold {
+        fn enter(&mut self, _: &mut Request, res: &mut Response) -> Status {
+            let told = self.told.lock().take().unwrap();
+            let mut stream = res.stream();
+            let _ = stream.write(b"first").and_then(|_| stream.flush());
+            spawn(proc() {
+                told.recv();
+                let _ = stream.write(b"second").and_then(|_| stream.flush());
+            });
+            Unwind
+        }
+    }
+
+    // Chunks flushed while the chain runs are only sent once it has
+    // returned; after that each is sent as soon as it is flushed.
+    #[test]
+    fn client_gets_chunks_flushed_after_the_chain_returns() {
+        let (tell, told) = channel();
+        let mut server: Server = Iron::new();
+        server.chain.link(StreamsWhenTold { told: Arc::new(Mutex::new(Some(told))) });
+        let (port, shutdown) = start(server);
+
+        let mut client = TcpStream::connect("127.0.0.1", port).unwrap();
+        client.write(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let mut wire = String::new();
+        while !wire.as_slice().ends_with("5\r\nfirst\r\n") {
+            wire.push_char(client.read_byte().unwrap() as char);
+        }
+
+        tell.send(());
+        let rest = String::from_utf8(client.read_to_end().unwrap()).unwrap();
+        assert_eq!(rest.as_slice(), "6\r\nsecond\r\n0\r\n\r\n");
+        shutdown.signal();
+    }
+
+    fn keeps_stream_in_alloy(req: &mut Request, res: &mut Response) -> Status {
+        let mut stream = res.stream();
+        let _ = stream.write(b"Hello").and_then(|_| stream.flush());
+        req.alloy.insert(stream);
+        Unwind
+    }
+
+    #[test]
+    fn ends_stream_left_in_alloy() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(keeps_stream_in_alloy));
+        let (port, shutdown) = start(server);
+
+        let mut client = TcpStream::connect("127.0.0.1", port).unwrap();
+        client.write(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let wire = String::from_utf8(client.read_to_end().unwrap()).unwrap();
+        assert!(wire.as_slice().ends_with("\r\n\r\n5\r\nHello\r\n0\r\n\r\n"));
+        shutdown.signal();
+    }
+
+    fn streams_many_chunks(_: &mut Request, res: &mut Response) -> Status {
+        let mut stream = res.stream();
+        for _ in range(0, STREAM_BUFFER + 1) {
+            let _ = stream.write(b"x").and_then(|_| stream.flush());
+        }
+        Unwind
+    }
+
+    #[test]
+    fn streams_chunks_flushed_within_the_chain() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(streams_many_chunks));
+        let (port, shutdown) = start(server);
+
+        let mut client = TcpStream::connect("127.0.0.1", port).unwrap();
+        client.write(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let wire = String::from_utf8(client.read_to_end().unwrap()).unwrap();
+        let body = "1\r\nx\r\n".repeat(STREAM_BUFFER + 1);
+        assert!(wire.as_slice().ends_with(format!("\r\n\r\n{}0\r\n\r\n", body).as_slice()));
+        shutdown.signal();
+    }
+
+    // Gives a first chunk, then fails.
+    struct FailingReader { done: bool }
+
+    impl Reader for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+            if self.done { return Err(standard_error(OtherIoError)) }
+            self.done = true;
+            buf[0] = b'x';
+            Ok(1)
+        }
+    }
+
+    fn sends_failing_reader(_: &mut Request, res: &mut Response) -> Status {
+        res.send_reader(FailingReader { done: false }, None);
+        Unwind
+    }
+
+    #[test]
+    fn closes_connection_when_body_fails() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(sends_failing_reader));
+        let (port, shutdown) = start(server);
+
+        // Kept alive unless the server gives up on the response.
+        let mut client = TcpStream::connect("127.0.0.1", port).unwrap();
+        client.write(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let wire = String::from_utf8(client.read_to_end().unwrap()).unwrap();
+        assert!(wire.as_slice().ends_with("\r\n\r\n1\r\nx\r\n"));
+        shutdown.signal();
+    }
+
+    fn get(port: u16) -> String {
+        let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+        stream.write(b"GET / HTTP/1.0\r\nHost: localhost\r\n\r\n").unwrap();
+        String::from_utf8(stream.read_to_end().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn listens_on_all_addresses() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(hello));
+        let shutdown = server.shutdown();
+        let loopback = SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 };
+        let bound = server.spawn_listeners([loopback, loopback]);
+
+        assert_eq!(bound.len(), 2);
+        assert!(bound[0].port != bound[1].port);
+        assert!(get(bound[0].port).as_slice().ends_with("Hello"));
+        assert!(get(bound[1].port).as_slice().ends_with("Hello"));
+        shutdown.signal();
+    }
+
+    #[test]
+    fn stops_accepting_on_shutdown() {
+        use std::io::timer::sleep;
+
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(hello));
+        let (port, shutdown) = start(server);
+        assert!(get(port).as_slice().ends_with("Hello"));
+
+        // The accept loop notices within `ACCEPT_POLL_MS`, and drops its
+        // socket, after which connecting is refused.
+        shutdown.signal();
+        let mut tries = 0u;
+        while TcpStream::connect("127.0.0.1", port).is_ok() {
+            tries += 1;
+            assert!(tries < 100, "The server still accepts connections.");
+            sleep(10);
+        }
+    }
+
+    fn echo_path(req: &mut Request, res: &mut Response) -> Status {
+        res.serve(::http::status::Ok, format!("[{}]", req.url.serialize_path().unwrap()));
+        Unwind
+    }
+
+    #[test]
+    fn answers_pipelined_requests_in_order() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(echo_path));
+        let (port, shutdown) = start(server);
+
+        let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+        stream.write(b"GET /first HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                       POST /second HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello\
+                       GET /third HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let wire = String::from_utf8(stream.read_to_end().unwrap()).unwrap();
+
+        let position = |path: &str| wire.as_slice().find_str(path).unwrap();
+        assert_eq!(wire.as_slice().split_str("HTTP/1.1 200 OK").count(), 4);
+        assert!(position("[/first]") < position("[/second]"));
+        assert!(position("[/second]") < position("[/third]"));
+        shutdown.signal();
+    }
+
+    fn echo_method(req: &mut Request, res: &mut Response) -> Status {
+        res.serve(::http::status::Ok, format!("[{}]", req.method));
+        Unwind
+    }
+
+    #[test]
+    fn keeps_patch_and_extension_methods() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(echo_method));
+        let (port, shutdown) = start(server);
+
+        let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+        stream.write(b"PATCH / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n\
+                       REPORT / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let wire = String::from_utf8(stream.read_to_end().unwrap()).unwrap();
+
+        assert!(wire.as_slice().contains("[PATCH]"));
+        assert!(wire.as_slice().contains("[REPORT]"));
+        shutdown.signal();
+    }
+
+    fn rejects_login(req: &mut Request, res: &mut Response) -> Status {
+        match req.url.serialize_path().unwrap().as_slice() {
+            "/login" => res.serve(::http::status::Unauthorized, "Unauthorized"),
+            _ => res.serve(::http::status::Ok, "Hello")
+        }
+        Unwind
+    }
+
+    #[test]
+    fn ignored_body_does_not_desync_next_request() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(rejects_login));
+        let (port, shutdown) = start(server);
+
+        let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+        stream.write(b"POST /login HTTP/1.1\r\nHost: localhost\r\nContent-Length: 27\r\n\r\n\
+                       user=admin&password=hunter2\
+                       GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let wire = String::from_utf8(stream.read_to_end().unwrap()).unwrap();
+
+        let unauthorized = wire.as_slice().find_str("HTTP/1.1 401 Unauthorized").unwrap();
+        let ok = wire.as_slice().find_str("HTTP/1.1 200 OK").unwrap();
+        assert!(unauthorized < ok);
+        assert!(wire.as_slice().ends_with("Hello"));
+        shutdown.signal();
+    }
+
+    #[test]
+    fn chunked_body_does_not_desync_next_request() {
+        let mut server: Server = Iron::new();
+        server.chain.link(FromFn::new(rejects_login));
+        let (port, shutdown) = start(server);
+
+        // The chunk smuggles a request for /login.
+        let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+        stream.set_read_timeout(Some(5000));
+        stream.write(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       28\r\nGET /login HTTP/1.1\r\nHost: localhost\r\n\r\n\r\n0\r\n\r\n").unwrap();
+
+        // Only returns once the server has closed the connection.
+        let wire = String::from_utf8(stream.read_to_end().unwrap()).unwrap();
+        assert!(wire.as_slice().starts_with("HTTP/1.1 411"));
+        assert!(!wire.as_slice().contains("401"));
+        shutdown.signal();
+    }
+
+    #[test]
+    fn closes_idle_keep_alive_connection() {
+        let mut server: Server = Iron::new().with_keep_alive_timeout(100);
+        server.chain.link(FromFn::new(hello));
+        let (port, shutdown) = start(server);
+
+        // An HTTP/1.1 request, whose connection would be kept alive.
+        let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+        stream.set_read_timeout(Some(5000));
+        stream.write(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        // Only returns once the server has closed the idle connection,
+        // well before the client's own timeout.
+        let wire = String::from_utf8(stream.read_to_end().unwrap()).unwrap();
+        assert!(!wire.as_slice().contains("Connection: close"));
+        assert!(wire.as_slice().ends_with("Hello"));
+        shutdown.signal();
+    }
+
+    // Fails the first `failures` accepts, then accepts from `inner`.
+    struct FailingAcceptor {
+        inner: TcpAcceptor,
+        failures: uint
+    }
+
+    impl Acceptor<TcpStream> for FailingAcceptor {
+        fn accept(&mut self) -> IoResult<TcpStream> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(standard_error(OtherIoError));
+            }
+            self.inner.accept()
+        }
+    }
+
+    impl Incoming for FailingAcceptor {
+        fn wait_at_most(&mut self, ms: u64) { self.inner.wait_at_most(ms) }
+    }
+
+    static mut ACCEPT_ERRORS: uint = 0;
+
+    fn count_accept_error(_: IoError) {
+        unsafe { ACCEPT_ERRORS += 1 }
+    }
+
+    #[test]
+    fn keeps_accepting_after_accept_errors() {
+        let mut server: Server = Iron::new().on_accept_error(count_accept_error);
+        server.chain.link(FromFn::new(hello));
+        let shutdown = server.shutdown();
+
+        let (acceptor, local) = bind(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 }).unwrap();
+        let listener = server.into_listener();
+        spawn(proc() { listener.accept(FailingAcceptor { inner: acceptor, failures: 3 }) });
+
+        assert!(get(local.port).as_slice().ends_with("Hello"));
+        assert_eq!(unsafe { ACCEPT_ERRORS }, 3);
+        shutdown.signal();
+    }
+
+    fn echo_user_agent_len(req: &mut Request, res: &mut Response) -> Status {
+        let len = req.headers.user_agent.as_ref().map_or(0, |agent| agent.len());
+        res.serve(::http::status::Ok, format!("[{}]", len));
+        Unwind
+    }
+
+    // A request whose User-Agent is `len` bytes long.
+    fn large_header_request(len: uint, connection: &str) -> String {
+        let agent = String::from_char(len, 'a');
+        format!("GET / HTTP/1.1\r\nHost: localhost\r\nUser-Agent: {}\r\nConnection: {}\r\n\r\n",
+                agent, connection)
+    }
+
+    #[test]
+    fn parses_large_headers_with_any_read_buffer_size() {
+        for &bytes in [0u, 1, 16, 64 * 1024].iter() {
+            let mut server: Server = Iron::new().with_read_buffer_size(bytes);
+            server.chain.link(FromFn::new(echo_user_agent_len));
+            let (port, shutdown) = start(server);
+
+            let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+            stream.set_read_timeout(Some(5000));
+            stream.write(large_header_request(4096, "close").as_bytes()).unwrap();
+            let wire = String::from_utf8(stream.read_to_end().unwrap()).unwrap();
+            assert!(wire.as_slice().ends_with("[4096]"));
+            shutdown.signal();
+        }
+    }
+
+    mod bench {
+        use test::Bencher;
+        use std::io::TcpStream;
+
+        use super::{start, echo_user_agent_len, large_header_request};
+        use super::super::{Iron, Server};
+        use super::super::super::chain::Chain;
+        use super::super::super::middleware::FromFn;
+
+        // Send requests with 8 KiB of headers, one after the other on a
+        // connection, to a server reading with a buffer of `bytes`.
+        fn bench_read_buffer(b: &mut Bencher, bytes: uint) {
+            let mut server: Server = Iron::new().with_read_buffer_size(bytes);
+            server.chain.link(FromFn::new(echo_user_agent_len));
+            let (port, shutdown) = start(server);
+
+            let request = large_header_request(8 * 1024, "keep-alive");
+            let mut stream = TcpStream::connect("127.0.0.1", port).unwrap();
+            let mut buf = [0u8, ..1024];
+            b.iter(|| {
+                stream.write(request.as_bytes()).unwrap();
+                let mut wire = vec![];
+                while !wire.as_slice().ends_with(b"[8192]") {
+                    let read = stream.read(buf).unwrap();
+                    wire.push_all(buf.slice_to(read));
+                }
+            });
+            shutdown.signal();
+        }
+
+        #[bench]
+        fn read_buffer_256(b: &mut Bencher) { bench_read_buffer(b, 256) }
+
+        #[bench]
+        fn read_buffer_4k(b: &mut Bencher) { bench_read_buffer(b, 4 * 1024) }
 
-        // Write the response back to http_res
-        res.write_back(http_res);
+        #[bench]
+        fn read_buffer_64k(b: &mut Bencher) { bench_read_buffer(b, 64 * 1024) }
     }
 }
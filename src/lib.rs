@@ -10,7 +10,7 @@
 #![deny(unused_variable)]
 #![deny(unnecessary_typecast)]
 
-#![feature(macro_rules, phase, globs)]
+#![feature(macro_rules, phase, globs, struct_variant)]
 //! The main crate for the Iron library.
 //!
 //! Iron is a high level web framework built in and for Rust.
@@ -24,10 +24,15 @@
 //! ```ignore
 //! #[deriving(Clone)]
 //! struct ResponseTime {
-//!     entry_time: u64
+//!     entry_time: u64,
+//!     logger: Box<Logger + Send>
 //! }
 //!
-//! impl ResponseTime { fn new() -> ResponseTime { ResponseTime { entry_time: 0u64 } } }
+//! impl ResponseTime {
+//!     fn new() -> ResponseTime {
+//!         ResponseTime { entry_time: 0u64, logger: box StdoutLogger as Box<Logger + Send> }
+//!     }
+//! }
 //!
 //! impl Middleware for ResponseTime {
 //!     fn enter(&mut self, _req: &mut Request, _res: &mut Response) -> Status {
@@ -37,7 +42,7 @@
 //!
 //!     fn exit(&mut self, _req: &mut Request, _res: &mut Response) -> Status {
 //!         let delta = precise_time_ns() - self.entry_time;
-//!         println!("Request took: {} ms", (delta as f64) / 100000.0);
+//!         self.logger.log(format!("Request took: {} ms", (delta as f64) / 1000000.0).as_slice());
 //!         Continue
 //!     }
 //! }
@@ -55,23 +60,82 @@ extern crate contenttype;
 extern crate http;
 extern crate anymap;
 extern crate url;
+extern crate time;
+extern crate serialize;
 #[cfg(test)]
 extern crate test;
 
-pub use request::Request;
-pub use response::Response;
+pub use request::{Request, BodyError, TooLarge, Authorization, Basic, Bearer, Other};
+pub use decode::{DecodeError, MissingField, BadValue, UnsupportedType, FormDecoder};
+pub use response::{Response, ResponseStream, InvalidValue, STREAM_BUFFER};
 
-pub use iron::{Iron, Server};
-pub use middleware::{Middleware, Status, Continue, Unwind, Error, FromFn};
+pub use iron::{Iron, Server, ErrorRenderer, FlushHook, AcceptErrorHook};
+pub use middleware::{Middleware, Status, Continue, Unwind, Error, ErrorStatus, FromFn, ErrorHandler};
+pub use middleware::{handled, next};
 
 pub use chain::Chain;
 pub use chain::stackchain::StackChain;
+pub use first_of::FirstOf;
 
 pub use alloy::Alloy;
 
+pub use logger::{Logger, StdoutLogger};
+pub use access_log::{AccessLog, LogContext};
+
+pub use shutdown::Shutdown;
+pub use entropy::Entropy;
+
+pub use require_https::RequireHttps;
+pub use canonical_host::CanonicalHost;
+pub use tee_body::TeeBody;
+pub use router::{Router, Pattern, Params, MatchedRoute};
+pub use set_header::SetHeader;
+pub use cache_policy::CachePolicy;
+pub use conditional_get::ConditionalGet;
+pub use method_override::MethodOverride;
+pub use csrf::{Csrf, CsrfToken, TokenStore, CookieStore};
+pub use query_limit::QueryLimit;
+pub use trusted_proxies::TrustedProxies;
+pub use timeout::{Timeout, Deadline};
+pub use toggle::Toggle;
+pub use normalize_path::NormalizePath;
+pub use options_ok::OptionsOk;
+pub use vary::Vary;
+
+mod macros;
 mod request;
 mod response;
 mod middleware;
 mod alloy;
+mod cache_policy;
+mod canonical_host;
+mod conditional_get;
+mod csrf;
+mod decode;
+mod entropy;
 mod chain;
+mod first_of;
 mod iron;
+mod logger;
+mod access_log;
+mod method_override;
+mod normalize_path;
+mod options_ok;
+mod protocol;
+mod query_limit;
+mod require_https;
+mod router;
+mod set_header;
+mod shutdown;
+mod sniff;
+mod tee_body;
+mod timeout;
+mod toggle;
+mod trusted_proxies;
+mod vary;
+
+pub mod sse;
+pub mod template;
+
+#[cfg(test)]
+mod mock;
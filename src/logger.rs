@@ -0,0 +1,78 @@
+//! Exposes the `Logger` trait, a pluggable sink for log output
+//! from `Middleware`.
+
+/// A destination for log messages.
+///
+/// `Middleware` which produce log output should hold a `Box<Logger + Send>`
+/// and write through it rather than printing directly, so that their output
+/// can be redirected to files, structured sinks or captured in tests:
+///
+/// ```ignore
+/// #[deriving(Clone)]
+/// struct Hello { logger: Box<Logger + Send> }
+///
+/// impl Middleware for Hello {
+///     fn enter(&mut self, _: &mut Request, _: &mut Response) -> Status {
+///         self.logger.log("Hello!");
+///         Continue
+///     }
+/// }
+///
+/// server.chain.link(Hello { logger: box StdoutLogger as Box<Logger + Send> });
+/// ```
+pub trait Logger: Send + Clone {
+    /// Write a single message to this sink.
+    fn log(&mut self, message: &str);
+
+    // Helper function to clone the Logger.
+    #[doc(hidden)]
+    fn clone_box(&self) -> Box<Logger + Send> { box self.clone() as Box<Logger + Send> }
+}
+
+impl Clone for Box<Logger + Send> {
+    fn clone(&self) -> Box<Logger + Send> { self.clone_box() }
+}
+
+/// The default `Logger`, which prints every message to stdout.
+#[deriving(Clone)]
+pub struct StdoutLogger;
+
+impl Logger for StdoutLogger {
+    fn log(&mut self, message: &str) {
+        println!("{}", message);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::method::Get;
+
+    use super::Logger;
+    use super::super::mock;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::middleware::{Middleware, Status, Continue};
+
+    #[deriving(Clone)]
+    struct Greeter {
+        logger: Box<Logger + Send>
+    }
+
+    impl Middleware for Greeter {
+        fn enter(&mut self, req: &mut Request, _: &mut Response) -> Status {
+            self.logger.log(format!("Hello, {}", req.url.serialize_path().unwrap()).as_slice());
+            Continue
+        }
+    }
+
+    #[test]
+    fn captures_middleware_output() {
+        let logger = mock::CaptureLogger::new();
+        let mut greeter = Greeter { logger: logger.boxed() };
+
+        let _ = greeter.enter(&mut mock::request(Get, "http://localhost/world"),
+                              &mut mock::response());
+
+        assert_eq!(logger.lines(), vec!["Hello, /world".to_string()]);
+    }
+}
@@ -0,0 +1,70 @@
+//! Macros for defining `Middleware` with less boilerplate.
+
+/// Define a `Middleware` from the bodies of its `enter` and/or `exit`.
+///
+/// This generates a unit struct deriving `Clone` and an implementation of
+/// `Middleware` for it, with a `new` constructor. Each body names the
+/// request and response it is given, and must evaluate to a `Status`.
+///
+/// ```
+/// #![feature(phase)]
+/// #[phase(plugin, link)] extern crate iron;
+/// extern crate http;
+///
+/// use iron::{Request, Response, Middleware, Chain, StackChain, Status, Continue, Unwind};
+///
+/// middleware!(PoweredBy {
+///     exit(_req, res) {
+///         res.set_header("X-Powered-By", "Iron");
+///         Continue
+///     }
+/// })
+///
+/// middleware!(Hello {
+///     enter(_req, res) {
+///         res.serve(http::status::Ok, "Hello, world!");
+///         Unwind
+///     }
+/// })
+///
+/// fn main() {
+///     let mut chain: StackChain = Chain::new();
+///     chain.link(PoweredBy::new());
+///     chain.link(Hello::new());
+/// }
+/// ```
+#[macro_export]
+macro_rules! middleware(
+    ($name:ident { enter($req:ident, $res:ident) $enter:block }) => (
+        middleware!($name {
+            enter($req, $res) $enter
+            exit(_req, _res) { ::iron::Continue }
+        })
+    );
+    ($name:ident { exit($req:ident, $res:ident) $exit:block }) => (
+        middleware!($name {
+            enter(_req, _res) { ::iron::Continue }
+            exit($req, $res) $exit
+        })
+    );
+    ($name:ident {
+        enter($ereq:ident, $eres:ident) $enter:block
+        exit($xreq:ident, $xres:ident) $exit:block
+    }) => (
+        #[deriving(Clone)]
+        struct $name;
+
+        impl $name {
+            #[allow(dead_code)]
+            fn new() -> $name { $name }
+        }
+
+        impl ::iron::Middleware for $name {
+            fn enter(&mut self, $ereq: &mut ::iron::Request,
+                     $eres: &mut ::iron::Response) -> ::iron::Status $enter
+
+            fn exit(&mut self, $xreq: &mut ::iron::Request,
+                    $xres: &mut ::iron::Response) -> ::iron::Status $exit
+        }
+    );
+)
@@ -0,0 +1,111 @@
+//! Exposes the `MethodOverride` middleware, which lets clients limited
+//! to GET and POST, such as HTML forms, make other requests.
+
+use std::ascii::StrAsciiExt;
+
+use http::method::{Method, Post, Put, Patch, Delete};
+use url::form_urlencoded;
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue};
+
+/// `Middleware` which changes the method of a `POST` request to the one
+/// given by its `X-HTTP-Method-Override` header or, failing that, by
+/// the `_method` field of its form body.
+///
+/// Only `PUT`, `PATCH` and `DELETE` may be requested. `MethodOverride`
+/// should be linked before any `Middleware` which looks at the method,
+/// such as a `Router`.
+#[deriving(Clone)]
+pub struct MethodOverride;
+
+impl MethodOverride {
+    /// Create a new `MethodOverride`.
+    pub fn new() -> MethodOverride { MethodOverride }
+}
+
+fn overriding_method(name: &str) -> Option<Method> {
+    match name.to_ascii_upper().as_slice() {
+        "PUT" => Some(Put),
+        "PATCH" => Some(Patch),
+        "DELETE" => Some(Delete),
+        _ => None
+    }
+}
+
+fn requested_method(req: &Request) -> Option<String> {
+    for (name, value) in req.headers.extensions.iter() {
+        if name.as_slice().eq_ignore_ascii_case("X-HTTP-Method-Override") {
+            return Some(value.clone());
+        }
+    }
+
+    form_urlencoded::parse(req.body.as_bytes()).move_iter()
+        .find(|&(ref name, _)| name.as_slice() == "_method")
+        .map(|(_, value)| value)
+}
+
+impl Middleware for MethodOverride {
+    fn enter(&mut self, req: &mut Request, _: &mut Response) -> Status {
+        if req.method != Post { return Continue }
+
+        match requested_method(req).and_then(|name| overriding_method(name.as_slice())) {
+            Some(method) => req.method = method,
+            None => ()
+        }
+        Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::method::{Method, Get, Post, Put, Delete};
+
+    use super::MethodOverride;
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::middleware::{Status, Unwind, FromFn};
+
+    fn seen(req: &mut Request, res: &mut Response) -> Status {
+        res.serve(::http::status::Ok, req.method.to_string());
+        Unwind
+    }
+
+    fn seen_method(mut req: Request) -> String {
+        let mut chain: StackChain = Chain::new();
+        chain.link(MethodOverride::new());
+        chain.link(FromFn::new(seen));
+
+        let mut res = mock::response();
+        let _ = chain.dispatch(&mut req, &mut res);
+        String::from_utf8(res.body.read_to_end().unwrap()).unwrap()
+    }
+
+    fn request(method: Method, body: &str) -> Request {
+        let mut req = mock::request(method, "http://localhost/posts/1");
+        req.body = body.to_string();
+        req
+    }
+
+    #[test]
+    fn overrides_from_form_field() {
+        assert_eq!(seen_method(request(Post, "title=x&_method=DELETE")), Delete.to_string());
+    }
+
+    #[test]
+    fn overrides_from_header() {
+        let mut req = request(Post, "");
+        let _ = req.headers.extensions.insert("X-HTTP-Method-Override".to_string(), "put".to_string());
+        assert_eq!(seen_method(req), Put.to_string());
+    }
+
+    #[test]
+    fn only_overrides_post() {
+        assert_eq!(seen_method(request(Get, "_method=DELETE")), Get.to_string());
+        assert_eq!(seen_method(request(Post, "_method=CONNECT")), Post.to_string());
+    }
+}
@@ -3,6 +3,8 @@
 
 use std::fmt::Show;
 
+use HttpStatus = http::status::Status;
+
 use super::response::Response;
 use super::request::Request;
 use super::chain::Chain;
@@ -38,6 +40,40 @@ pub enum Status {
     Error(Box<Show>)
 }
 
+/// The request has been handled: stop going down the `Chain` and start
+/// calling `exit`. The same as `Unwind`, under a name which says so.
+///
+/// ```ignore
+/// fn hello(_: &mut Request, res: &mut Response) -> Status {
+///     res.serve(http::status::Ok, "Hello!");
+///     handled()
+/// }
+/// ```
+#[inline]
+pub fn handled() -> Status { Unwind }
+
+/// Pass the request on to the next `Middleware` in the `Chain`. The same as
+/// `Continue`, under a name which says so.
+#[inline]
+pub fn next() -> Status { Continue }
+
+/// The status a request whose `Chain` returned `Error` should be answered
+/// with, if no `Middleware` serves a response from `on_error`.
+///
+/// Without one the request is treated as a server error, a `500`. To
+/// blame the client instead, store a 4xx status in `Request::alloy`
+/// before returning `Error`:
+///
+/// ```ignore
+/// req.alloy.insert(ErrorStatus(http::status::BadRequest));
+/// return Error(box "Malformed JSON body." as Box<Show>);
+/// ```
+///
+/// The server removes it from `alloy` once the request has been
+/// dispatched, and before passing an unhandled request to the fallback.
+#[deriving(Clone, PartialEq, Show)]
+pub struct ErrorStatus(pub HttpStatus);
+
 /// All middleware should implement `Middleware`, which allows it to be `linked`
 /// to a `Chain` so that it will be called for each incoming request.
 ///
@@ -46,14 +82,17 @@ pub enum Status {
 /// other `Middleware` further down the stack or to terminal controllers.
 ///
 /// Internal data should be stored on the `struct` that implements `Middleware`
-/// itself. All `Middleware` are cloned for each client request, so the object
-/// initially linked to the `Iron` instance will be provided as `&mut self` to
-/// enter for every request.
+/// itself. All `Middleware` are cloned, along with their `Chain`, for each client
+/// connection, so a clone of the object initially linked to the `Iron` instance
+/// will be provided as `&mut self` to enter for every request.
 ///
-/// Data stored on a `Middleware` instance does _not_ persist
-/// between requests and is _not_ shared between different, concurrent, requests.
-/// The same is true for data stored on `Request::alloy`. Should you need to persist
-/// data between requests, you should use an `Arc` within your `Middleware`.
+/// Data stored on a `Middleware` instance is _not_ shared between different,
+/// concurrent, requests: a clone only ever handles one request at a time.
+/// Requests on the same keep-alive connection are handled by the same clone,
+/// one after another, so per-request data should be reset in `enter` rather
+/// than relied upon to start out empty. Data stored on `Request::alloy` does
+/// not persist between requests at all. Should you need to share data between
+/// requests, you should use an `Arc` within your `Middleware`.
 ///
 /// External data should be stored in `Request::alloy`.
 /// `Alloy` is a thin wrapper around `AnyMap` and is effectively a
@@ -134,7 +173,7 @@ impl Middleware for Box<Chain + Send> {
 /// and a `&mut Response` and returns a `Status`. For example:
 ///
 /// ```ignore
-/// fn hello_world(&mut Request, &mut Response) -> Status {
+/// fn hello_world(_: &mut Request, res: &mut Response) -> Status {
 ///     res.serve(http::status::Ok, b"Hello World!");
 ///     Continue
 /// }
@@ -142,6 +181,8 @@ impl Middleware for Box<Chain + Send> {
 /// server.chain.link(FromFn::new(hello_world));
 /// ```
 ///
+/// The function borrows the request mutably for the whole call, so it can
+/// use any of its methods, or take the body out of it.
 pub struct FromFn {
     func: fn(&mut Request, &mut Response) -> Status
 }
@@ -169,3 +210,154 @@ impl Middleware for FromFn {
     }
 }
 
+/// Wrapper struct which allows a bare function to be used as an error
+/// handler for the whole `Chain`.
+///
+/// `ErrorHandler` does nothing on `enter` and `exit`, and calls its
+/// function from `on_error`. Link it before any `Middleware` which may
+/// return `Error` to centralize error rendering or logging:
+///
+/// ```ignore
+/// fn log_error(_: &mut Request, res: &mut Response, err: &mut Show) {
+///     error!("Error when handling request: {}", err);
+///     res.serve(http::status::InternalServerError, "Internal Server Error");
+/// }
+///
+/// server.chain.link(ErrorHandler::new(log_error));
+/// ```
+///
+pub struct ErrorHandler {
+    func: fn(&mut Request, &mut Response, &mut Show)
+}
+
+impl ErrorHandler {
+    /// Constructs a new ErrorHandler given a fn of the correct signature.
+    pub fn new(func: fn(&mut Request, &mut Response, &mut Show)) -> ErrorHandler {
+        ErrorHandler {
+            func: func
+        }
+    }
+}
+
+impl Clone for ErrorHandler {
+    fn clone(&self) -> ErrorHandler {
+        ErrorHandler {
+            func: self.func
+        }
+    }
+}
+
+impl Middleware for ErrorHandler {
+    fn on_error(&mut self, req: &mut Request, res: &mut Response, err: &mut Show) {
+        (self.func)(req, res, err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt::Show;
+    use std::mem;
+    use http::method::{Get, Post};
+    use http::status::{InternalServerError, Forbidden};
+
+    use std::sync::{Arc, Mutex};
+
+    use super::{Middleware, Status, Error, FromFn, ErrorHandler, handled, next};
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::Response;
+
+    fn fails(_: &mut Request, _: &mut Response) -> Status {
+        Error(box "failed" as Box<Show>)
+    }
+
+    fn handle(_: &mut Request, res: &mut Response, err: &mut Show) {
+        res.serve(InternalServerError, format!("handled: {}", err));
+    }
+
+    #[test]
+    fn error_handler_called_on_error() {
+        let mut chain: StackChain = Chain::new();
+        chain.link(ErrorHandler::new(handle));
+        chain.link(FromFn::new(fails));
+
+        let mut res = mock::response();
+        let _ = chain.dispatch(&mut mock::request(Get, "http://localhost/"), &mut res);
+
+        assert_eq!(res.status, Some(InternalServerError));
+        assert_eq!(res.body.read_to_end().unwrap(), b"handled: failed".to_vec());
+    }
+
+    #[deriving(Clone)]
+    struct Entered { count: Arc<Mutex<uint>> }
+
+    impl Middleware for Entered {
+        fn enter(&mut self, _: &mut Request, _: &mut Response) -> Status {
+            *self.count.lock() += 1;
+            next()
+        }
+    }
+
+    fn handles(_: &mut Request, res: &mut Response) -> Status {
+        res.serve(::http::status::Ok, "handled");
+        handled()
+    }
+
+    #[test]
+    fn handled_stops_traversal() {
+        let count = Arc::new(Mutex::new(0u));
+        let mut chain: StackChain = Chain::new();
+        chain.link(Entered { count: count.clone() });
+        chain.link(FromFn::new(handles));
+        chain.link(Entered { count: count.clone() });
+
+        let mut res = mock::response();
+        let _ = chain.dispatch(&mut mock::request(Get, "http://localhost/"), &mut res);
+
+        assert_eq!(*count.lock(), 1);
+        assert_eq!(res.body.read_to_end().unwrap(), b"handled".to_vec());
+    }
+
+    fn forbids(_: &mut Request, res: &mut Response) -> Status {
+        res.with_status(Forbidden).unwind()
+    }
+
+    #[test]
+    fn fluent_unwind_stops_with_status() {
+        let count = Arc::new(Mutex::new(0u));
+        let mut chain: StackChain = Chain::new();
+        chain.link(FromFn::new(forbids));
+        chain.link(Entered { count: count.clone() });
+
+        let mut req = mock::request(Get, "http://localhost/admin");
+        let mut res = mock::response();
+        let _ = chain.dispatch(&mut req, &mut res);
+        assert_eq!(*count.lock(), 0);
+
+        let mut wire = vec![];
+        res.write_to(&req, &mut wire).unwrap();
+        assert!(String::from_utf8(wire).unwrap().as_slice().starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    }
+
+    fn echoes_body(req: &mut Request, res: &mut Response) -> Status {
+        let body = mem::replace(&mut req.body, String::new());
+        res.serve(::http::status::Ok, body);
+        handled()
+    }
+
+    #[test]
+    fn from_fn_can_take_the_body() {
+        let mut chain: StackChain = Chain::new();
+        chain.link(FromFn::new(echoes_body));
+
+        let mut req = mock::request(Post, "http://localhost/echo");
+        req.body = "name=iron".to_string();
+        let mut res = mock::response();
+        let _ = chain.dispatch(&mut req, &mut res);
+
+        assert_eq!(res.body.read_to_end().unwrap(), b"name=iron".to_vec());
+        assert_eq!(req.body, String::new());
+    }
+}
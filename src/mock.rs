@@ -0,0 +1,77 @@
+//! Lightweight `Request` and `Response` constructors for tests.
+
+use std::io::net::ip::{SocketAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+
+use http::server::request::{AbsoluteUri, AbsolutePath};
+use http::headers::request::HeaderCollection;
+use http::headers::host::Host;
+use http::method::Method;
+use url::Url;
+
+use super::request::{HttpRequest, Request};
+use super::response::Response;
+use super::logger::Logger;
+
+/// Create a `Request` for `url` with the given method, an empty body
+/// and no headers.
+pub fn request(method: Method, url: &str) -> Request {
+    let mut req = http_request(method, "");
+    req.headers = box HeaderCollection::new();
+    req.request_uri = AbsoluteUri(Url::parse(url).unwrap());
+
+    let local = SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 3000 };
+    Request::from_http(req, local).unwrap()
+}
+
+/// Create an HTTP/1.1 `HttpRequest` for `path` on `localhost:3000`, as
+/// it would be handed to the server by the parser.
+pub fn http_request(method: Method, path: &str) -> HttpRequest {
+    let mut headers = box HeaderCollection::new();
+    headers.host = Some(Host { name: "localhost".to_string(), port: Some(3000) });
+
+    HttpRequest {
+        remote_addr: None,
+        headers: headers,
+        body: String::new(),
+        method: method,
+        request_uri: AbsolutePath(path.to_string()),
+        close_connection: false,
+        version: (1, 1)
+    }
+}
+
+/// Create an empty `Response` with no status set.
+pub fn response() -> Response {
+    Response::new()
+}
+
+/// A `Logger` which keeps every message, for asserting on log output.
+#[deriving(Clone)]
+pub struct CaptureLogger {
+    /// The messages logged so far, shared by all clones.
+    pub lines: Arc<Mutex<Vec<String>>>
+}
+
+impl CaptureLogger {
+    /// Create a new `CaptureLogger` with no messages.
+    pub fn new() -> CaptureLogger {
+        CaptureLogger { lines: Arc::new(Mutex::new(vec![])) }
+    }
+
+    /// A boxed clone of this logger, sharing its messages.
+    pub fn boxed(&self) -> Box<Logger + Send> {
+        box self.clone() as Box<Logger + Send>
+    }
+
+    /// The messages logged so far.
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().clone()
+    }
+}
+
+impl Logger for CaptureLogger {
+    fn log(&mut self, message: &str) {
+        self.lines.lock().push(message.to_string());
+    }
+}
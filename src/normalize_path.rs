@@ -0,0 +1,172 @@
+//! Exposes the `NormalizePath` middleware, which gives every request
+//! path a single canonical form.
+
+use http::status::{MovedPermanently, BadRequest};
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue, Unwind};
+
+/// `Middleware` which collapses empty segments and resolves `.` and `..`
+/// segments in `Request::path`, so that `/a//b/./c/..` is handled as `/a/b`.
+///
+/// `..` segments which would go above the root are rejected with a
+/// `400 Bad Request`. With `redirect`, requests for a path which is not
+/// canonical are redirected to the canonical one with a
+/// `301 Moved Permanently` instead of being rewritten:
+///
+/// ```ignore
+/// server.chain.link(NormalizePath::new().redirect());
+/// server.chain.link(router);
+/// ```
+///
+/// The parser already resolves literal dot-segments, so this mostly
+/// catches percent-encoded ones such as `%2e%2e`, which decode to `..`.
+/// It must be linked before any `Middleware` which matches on the path,
+/// such as a `Router`.
+#[deriving(Clone)]
+pub struct NormalizePath {
+    redirect: bool
+}
+
+impl NormalizePath {
+    /// Create a new `NormalizePath` which rewrites the path in place.
+    pub fn new() -> NormalizePath {
+        NormalizePath { redirect: false }
+    }
+
+    /// Redirect to the canonical path instead of rewriting it.
+    pub fn redirect(mut self) -> NormalizePath {
+        self.redirect = true;
+        self
+    }
+}
+
+// The canonical form of `path`, or None if it goes above the root.
+//
+// A trailing slash is kept, since `/dir/` and `/dir` are different
+// resources, and dot-segments at the end leave one, as in `/dir/..`.
+fn normalize(path: &[String]) -> Option<Vec<String>> {
+    let mut normalized: Vec<String> = vec![];
+    for (i, segment) in path.iter().enumerate() {
+        let last = i + 1 == path.len();
+        match segment.as_slice() {
+            "." => (),
+            ".." => if normalized.pop().is_none() { return None },
+            "" if !last => (),
+            _ => normalized.push(segment.clone())
+        }
+
+        let dot = segment.as_slice() == "." || segment.as_slice() == "..";
+        if last && dot { normalized.push(String::new()) }
+    }
+
+    // The root path is a single empty segment.
+    if normalized.is_empty() && !path.is_empty() { normalized.push(String::new()) }
+    Some(normalized)
+}
+
+// Percent-encode a decoded path segment.
+fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::new();
+    for byte in segment.bytes() {
+        let c = byte as char;
+        if c.is_alphanumeric() && byte < 0x80 || "-._~!$&'()*+,;=:@".contains_char(c) {
+            encoded.push_char(c);
+        } else {
+            encoded.push_str(format!("%{:02X}", byte).as_slice());
+        }
+    }
+    encoded
+}
+
+impl Middleware for NormalizePath {
+    fn enter(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        let normalized = match normalize(req.path.as_slice()) {
+            Some(normalized) => normalized,
+            None => {
+                res.serve(BadRequest, "Bad Request");
+                return Unwind;
+            }
+        };
+
+        if normalized == req.path { return Continue }
+
+        if self.redirect {
+            let mut url = req.url.clone();
+            match url.path_mut() {
+                Some(path) => *path = normalized.iter().map(|s| encode_segment(s.as_slice())).collect(),
+                None => return Continue
+            }
+            res.headers.location = Some(url);
+            res.serve(MovedPermanently, "");
+            Unwind
+        } else {
+            req.path = normalized;
+            Continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::method::Get;
+    use http::status::{MovedPermanently, BadRequest};
+
+    use super::NormalizePath;
+    use super::super::mock;
+    use super::super::middleware::{Middleware, Continue, Unwind};
+
+    fn path(segments: &[&str]) -> Vec<String> {
+        segments.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn collapses_duplicate_slashes() {
+        let mut req = mock::request(Get, "http://localhost//a//b/");
+        match NormalizePath::new().enter(&mut req, &mut mock::response()) {
+            Continue => (),
+            _ => fail!("NormalizePath did not continue.")
+        }
+        assert_eq!(req.path, path(["a", "b", ""]));
+    }
+
+    #[test]
+    fn keeps_root_path() {
+        let mut req = mock::request(Get, "http://localhost/");
+        let mut res = mock::response();
+        match NormalizePath::new().redirect().enter(&mut req, &mut res) {
+            Continue => (),
+            _ => fail!("NormalizePath redirected the root path.")
+        }
+        assert_eq!(req.path, path([""]));
+    }
+
+    #[test]
+    fn resolves_encoded_dot_segments() {
+        let mut req = mock::request(Get, "http://localhost/a/%2e/b/%2E%2E/c");
+        let _ = NormalizePath::new().enter(&mut req, &mut mock::response());
+        assert_eq!(req.path, path(["a", "c"]));
+    }
+
+    #[test]
+    fn redirects_to_canonical_path() {
+        let mut req = mock::request(Get, "http://localhost//a/%2e%2e/some%20page?q=1");
+        let mut res = mock::response();
+        match NormalizePath::new().redirect().enter(&mut req, &mut res) {
+            Unwind => (),
+            _ => fail!("NormalizePath did not redirect.")
+        }
+        assert_eq!(res.status, Some(MovedPermanently));
+        assert_eq!(res.headers.location.unwrap().serialize().as_slice(),
+                   "http://localhost/some%20page?q=1");
+    }
+
+    #[test]
+    fn rejects_root_escape() {
+        let mut req = mock::request(Get, "http://localhost/a/%2e%2e/%2e%2e/etc/passwd");
+        let mut res = mock::response();
+        let _ = NormalizePath::new().enter(&mut req, &mut res);
+        assert_eq!(res.status, Some(BadRequest));
+    }
+}
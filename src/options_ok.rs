@@ -0,0 +1,111 @@
+//! Exposes the `OptionsOk` middleware, which answers `OPTIONS` requests
+//! no route handles.
+
+use http::method::{Method, Options};
+use http::status::NoContent;
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue};
+
+/// `Middleware` which answers any `OPTIONS` request with a `204 No Content`
+/// and an `Allow` header listing the configured methods.
+///
+/// The request is answered in `exit`, and only if no other `Middleware`
+/// set a status, so `OptionsOk` can be linked first while a more specific
+/// route for `OPTIONS` still takes precedence:
+///
+/// ```ignore
+/// server.chain.link(OptionsOk::new(vec![Get, Head, Post, Options]));
+/// server.chain.link(router);
+/// ```
+#[deriving(Clone)]
+pub struct OptionsOk {
+    allow: Vec<Method>
+}
+
+impl OptionsOk {
+    /// Create a new `OptionsOk` allowing `allow`.
+    pub fn new(allow: Vec<Method>) -> OptionsOk {
+        OptionsOk { allow: allow }
+    }
+}
+
+impl Middleware for OptionsOk {
+    fn exit(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        if req.method == Options && res.status.is_none() {
+            res.headers.allow = Some(self.allow.clone());
+            res.serve(NoContent, "");
+        }
+        Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::method::{Method, Get, Post, Options};
+    use http::status::NoContent;
+    use OkStatus = http::status::Ok;
+
+    use super::OptionsOk;
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::router::Router;
+    use super::super::middleware::{Status, Unwind, FromFn};
+
+    fn described(_: &mut Request, res: &mut Response) -> Status {
+        res.serve(OkStatus, "Users");
+        Unwind
+    }
+
+    fn respond(method: Method, path: &str) -> Response {
+        let mut router = Router::new();
+        router.route(Options, "/users", FromFn::new(described));
+        router.route(Get, "/users", FromFn::new(described));
+
+        let mut chain: StackChain = Chain::new();
+        chain.link(OptionsOk::new(vec![Get, Post, Options]));
+        chain.link(router);
+
+        let mut res = mock::response();
+        let _ = chain.dispatch(&mut mock::request(method, format!("http://localhost{}", path).as_slice()),
+                               &mut res);
+        res
+    }
+
+    #[test]
+    fn answers_options_with_allow() {
+        let res = respond(Options, "/");
+        assert_eq!(res.status, Some(NoContent));
+        assert!(res.header_pairs().contains(&("Allow".to_string(), "GET, POST, OPTIONS".to_string())));
+    }
+
+    #[test]
+    fn sends_no_body_headers() {
+        let mut res = respond(Options, "/");
+        let mut wire = vec![];
+        res.write_to(&mock::request(Options, "http://localhost/"), &mut wire).unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(wire.as_slice().starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(!wire.as_slice().contains("Content-Length"));
+        assert!(!wire.as_slice().contains("Content-Type"));
+        assert!(wire.as_slice().ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn passes_other_methods_through() {
+        let res = respond(Get, "/");
+        assert_eq!(res.status, None);
+        assert_eq!(res.headers.allow, None);
+    }
+
+    #[test]
+    fn yields_to_options_routes() {
+        let mut res = respond(Options, "/users");
+        assert_eq!(res.status, Some(OkStatus));
+        assert_eq!(res.body.read_to_end().unwrap(), b"Users".to_vec());
+    }
+}
@@ -0,0 +1,366 @@
+//! Checks applied to each parsed request before it is dispatched.
+//!
+//! Requests failing these checks are answered directly by the server
+//! and never reach the `Chain`.
+
+use http::status::{Status, BadRequest, HttpVersionNotSupported, RequestUriTooLong,
+                   RequestHeaderFieldsTooLarge, ExpectationFailed, LengthRequired};
+use http::server::request::{Star, AbsoluteUri, AbsolutePath, Authority};
+use http::method::{Options, Connect};
+use http::headers::HeaderEnum;
+use std::ascii::StrAsciiExt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUint, SeqCst};
+
+use super::request::HttpRequest;
+
+/// Limits on the size of requests, configured on `Iron`.
+#[deriving(Clone)]
+pub struct Limits {
+    /// The maximum length of the request URI, in bytes.
+    pub max_uri_bytes: uint,
+
+    /// The maximum number of distinct request header names.
+    ///
+    /// The parser folds repeated headers into one before the limit is
+    /// checked, so this does not bound the number of header lines.
+    pub max_header_count: uint
+}
+
+impl Limits {
+    /// The default limits.
+    pub fn new() -> Limits {
+        Limits {
+            max_uri_bytes: 8 * 1024,
+            max_header_count: 100
+        }
+    }
+}
+
+/// A number of bytes of request bodies which may be handled at once across
+/// all connections, configured with `Iron::with_max_handled_body_bytes`.
+///
+/// Clones share the same budget.
+#[deriving(Clone)]
+pub struct BodyBudget {
+    remaining: Arc<AtomicUint>
+}
+
+/// A part of a `BodyBudget`, given back when it is dropped.
+pub struct BodyReservation {
+    remaining: Arc<AtomicUint>,
+    bytes: uint
+}
+
+impl BodyBudget {
+    /// A budget of `bytes`.
+    pub fn new(bytes: uint) -> BodyBudget {
+        BodyBudget { remaining: Arc::new(AtomicUint::new(bytes)) }
+    }
+
+    /// Take `bytes` out of the budget until the reservation is dropped,
+    /// or give `None` if fewer remain.
+    pub fn reserve(&self, bytes: uint) -> Option<BodyReservation> {
+        let mut remaining = self.remaining.load(SeqCst);
+        loop {
+            if remaining < bytes { return None }
+            let previous = self.remaining.compare_and_swap(remaining, remaining - bytes, SeqCst);
+            if previous == remaining { break }
+            remaining = previous;
+        }
+        Some(BodyReservation { remaining: self.remaining.clone(), bytes: bytes })
+    }
+}
+
+impl Drop for BodyReservation {
+    fn drop(&mut self) {
+        let _ = self.remaining.fetch_add(self.bytes, SeqCst);
+    }
+}
+
+/// Why a parsed request can't be dispatched.
+///
+/// Requests the parser could not make sense of at all are rejected with
+/// the status it gives, and connections failing while a request is read
+/// are closed; neither gets this far.
+#[deriving(Clone, PartialEq, Show)]
+pub enum ParseError {
+    /// The request line is well-formed, but its target can't go with its
+    /// method: only `OPTIONS` may ask for `*`, and only `CONNECT` for an
+    /// authority.
+    MalformedRequestLine,
+
+    /// The request is not HTTP/1.0 or HTTP/1.1.
+    UnsupportedVersion,
+
+    /// The request URI is longer than `Limits::max_uri_bytes`.
+    UriTooLong,
+
+    /// The request has more distinct headers than
+    /// `Limits::max_header_count`.
+    HeadersTooLarge,
+
+    /// The length of the body is ambiguous: the request is framed by both
+    /// `Transfer-Encoding` and `Content-Length`.
+    ///
+    /// Conflicting repeated `Content-Length` headers are not caught. The
+    /// parser keeps a single value for them and drops the raw header
+    /// lines, so there is nothing left to compare.
+    BadContentLength,
+
+    /// The body is sent with `Transfer-Encoding`. The parser only reads
+    /// bodies framed by `Content-Length`, so such a body would be left on
+    /// the connection and read as the next request.
+    UnsupportedTransferEncoding,
+
+    /// The request has an `Expect` header other than `100-continue`, the
+    /// only expectation the server knows how to meet.
+    UnmetExpectation
+}
+
+impl ParseError {
+    /// The status the request should be rejected with.
+    pub fn status(&self) -> Status {
+        match *self {
+            MalformedRequestLine => BadRequest,
+            UnsupportedVersion => HttpVersionNotSupported,
+            UriTooLong => RequestUriTooLong,
+            HeadersTooLarge => RequestHeaderFieldsTooLarge,
+            BadContentLength => BadRequest,
+            UnsupportedTransferEncoding => LengthRequired,
+            UnmetExpectation => ExpectationFailed
+        }
+    }
+}
+
+/// Check that `req` can safely be dispatched, or give the reason it
+/// should be rejected.
+pub fn check(req: &HttpRequest, limits: &Limits) -> Result<(), ParseError> {
+    // Only HTTP/1.0 and HTTP/1.1 are spoken.
+    match req.version {
+        (1, 0) | (1, 1) => (),
+        _ => return Err(UnsupportedVersion)
+    }
+
+    match req.request_uri {
+        Star if req.method != Options => return Err(MalformedRequestLine),
+        Authority(_) if req.method != Connect => return Err(MalformedRequestLine),
+        _ => ()
+    }
+
+    if uri_len(req) > limits.max_uri_bytes {
+        return Err(UriTooLong);
+    }
+
+    // Repeated headers are folded into one by the parser, so this counts
+    // distinct header names.
+    if req.headers.iter().count() > limits.max_header_count {
+        return Err(HeadersTooLarge);
+    }
+
+    // A request framed by both Transfer-Encoding and Content-Length can
+    // be read differently by intermediaries, and is the basis of request
+    // smuggling. Repeated Content-Length headers are folded into a single
+    // value by the parser, so conflicting ones can't be detected here.
+    if req.headers.transfer_encoding.is_some() && req.headers.content_length.is_some() {
+        return Err(BadContentLength);
+    }
+
+    // The client is asked to send the body again with a Content-Length.
+    if req.headers.transfer_encoding.is_some() {
+        return Err(UnsupportedTransferEncoding);
+    }
+
+    let unmet = req.headers.iter().any(|header| {
+        header.header_name().as_slice().eq_ignore_ascii_case("Expect") &&
+            !header.header_value().as_slice().trim().eq_ignore_ascii_case("100-continue")
+    });
+    if unmet {
+        return Err(UnmetExpectation);
+    }
+
+    Ok(())
+}
+
+// The length of the request URI, in bytes.
+fn uri_len(req: &HttpRequest) -> uint {
+    match req.request_uri {
+        Star => 1,
+        AbsoluteUri(ref url) => url.serialize().len(),
+        AbsolutePath(ref path) => path.len(),
+        Authority(ref authority) => authority.len()
+    }
+}
+
+/// The number of bytes `req` took on the wire: its request line, headers
+/// and body.
+///
+/// The parser does not keep the raw bytes, so this is the size of the
+/// request as reserialized from what was parsed. Whitespace the client
+/// added around header values, or headers it repeated, are not counted.
+pub fn wire_size(req: &HttpRequest) -> uint {
+    // "METHOD URI HTTP/x.y\r\n"
+    let request_line = req.method.to_string().len() + 1 + uri_len(req) + 1 + 8 + 2;
+
+    // "Name: value\r\n" for each header, then "\r\n".
+    let headers = req.headers.iter().fold(0, |total, header| {
+        total + header.header_name().len() + 2 + header.header_value().len() + 2
+    }) + 2;
+
+    request_line + headers + req.body.len()
+}
+
+#[cfg(test)]
+mod test {
+    use http::method::Post;
+    use http::method::{Get, Options, Connect};
+    use http::status::{BadRequest, HttpVersionNotSupported, RequestUriTooLong,
+                       RequestHeaderFieldsTooLarge, ExpectationFailed, LengthRequired};
+    use http::server::request::{Star, Authority};
+    use http::headers::transfer_encoding::Chunked;
+
+    use super::{check, wire_size, Limits, BodyBudget};
+    use super::{MalformedRequestLine, UnsupportedVersion, UriTooLong, HeadersTooLarge,
+                BadContentLength, UnsupportedTransferEncoding, UnmetExpectation};
+    use super::super::mock;
+    use super::super::request::HttpRequest;
+
+    #[test]
+    fn accepts_content_length() {
+        let mut req = mock::http_request(Post, "/");
+        req.headers.content_length = Some(5);
+        assert_eq!(check(&req, &Limits::new()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_chunked() {
+        let mut req = mock::http_request(Post, "/");
+        req.headers.transfer_encoding = Some(vec![Chunked]);
+        assert_eq!(check(&req, &Limits::new()), Err(UnsupportedTransferEncoding));
+    }
+
+    #[test]
+    fn rejects_chunked_with_content_length() {
+        let mut req = mock::http_request(Post, "/");
+        req.headers.transfer_encoding = Some(vec![Chunked]);
+        req.headers.content_length = Some(5);
+        assert_eq!(check(&req, &Limits::new()), Err(BadContentLength));
+    }
+
+    #[test]
+    fn accepts_http_1() {
+        let mut req = mock::http_request(Get, "/");
+        req.version = (1, 0);
+        assert_eq!(check(&req, &Limits::new()), Ok(()));
+        req.version = (1, 1);
+        assert_eq!(check(&req, &Limits::new()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        let mut req = mock::http_request(Get, "/");
+        req.version = (0, 9);
+        assert_eq!(check(&req, &Limits::new()), Err(UnsupportedVersion));
+        req.version = (2, 0);
+        assert_eq!(check(&req, &Limits::new()), Err(UnsupportedVersion));
+    }
+
+    #[test]
+    fn rejects_target_not_matching_method() {
+        let mut req = mock::http_request(Options, "/");
+        req.request_uri = Star;
+        assert_eq!(check(&req, &Limits::new()), Ok(()));
+        req.method = Get;
+        assert_eq!(check(&req, &Limits::new()), Err(MalformedRequestLine));
+
+        let mut req = mock::http_request(Connect, "/");
+        req.request_uri = Authority("localhost:443".to_string());
+        assert_eq!(check(&req, &Limits::new()), Ok(()));
+        req.method = Get;
+        assert_eq!(check(&req, &Limits::new()), Err(MalformedRequestLine));
+    }
+
+    #[test]
+    fn rejects_bodies_over_budget_until_released() {
+        let budget = BodyBudget::new(10);
+        let first = budget.reserve(6);
+        assert!(first.is_some());
+        assert!(budget.clone().reserve(6).is_none());
+        assert!(budget.reserve(4).is_some());
+
+        drop(first);
+        assert!(budget.reserve(6).is_some());
+    }
+
+    #[test]
+    fn maps_errors_to_status() {
+        assert_eq!(MalformedRequestLine.status(), BadRequest);
+        assert_eq!(UnsupportedVersion.status(), HttpVersionNotSupported);
+        assert_eq!(UriTooLong.status(), RequestUriTooLong);
+        assert_eq!(HeadersTooLarge.status(), RequestHeaderFieldsTooLarge);
+        assert_eq!(BadContentLength.status(), BadRequest);
+        assert_eq!(UnsupportedTransferEncoding.status(), LengthRequired);
+        assert_eq!(UnmetExpectation.status(), ExpectationFailed);
+    }
+
+    fn expecting(value: &str) -> HttpRequest {
+        let mut req = mock::http_request(Post, "/upload");
+        let _ = req.headers.extensions.insert("Expect".to_string(), value.to_string());
+        req
+    }
+
+    #[test]
+    fn accepts_expect_continue() {
+        assert_eq!(check(&expecting("100-continue"), &Limits::new()), Ok(()));
+        assert_eq!(check(&expecting("100-Continue"), &Limits::new()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_unknown_expectations() {
+        assert_eq!(check(&expecting("foo"), &Limits::new()), Err(UnmetExpectation));
+    }
+
+    #[test]
+    fn accepts_uri_within_limit() {
+        let limits = Limits { max_uri_bytes: 16, ..Limits::new() };
+        let req = mock::http_request(Get, "/0123456789abcde");
+        assert_eq!(check(&req, &limits), Ok(()));
+    }
+
+    #[test]
+    fn rejects_uri_over_limit() {
+        let limits = Limits { max_uri_bytes: 16, ..Limits::new() };
+        let req = mock::http_request(Get, "/0123456789abcdef");
+        assert_eq!(check(&req, &limits), Err(UriTooLong));
+    }
+
+    fn with_headers(count: uint) -> HttpRequest {
+        let mut req = mock::http_request(Get, "/");
+        // The Host header makes one.
+        for i in range(1, count) {
+            let _ = req.headers.extensions.insert(format!("X-Header-{}", i), "x".to_string());
+        }
+        req
+    }
+
+    #[test]
+    fn accepts_headers_within_count() {
+        let limits = Limits { max_header_count: 3, ..Limits::new() };
+        assert_eq!(check(&with_headers(3), &limits), Ok(()));
+    }
+
+    #[test]
+    fn rejects_headers_over_count() {
+        let limits = Limits { max_header_count: 3, ..Limits::new() };
+        assert_eq!(check(&with_headers(4), &limits), Err(HeadersTooLarge));
+    }
+
+    #[test]
+    fn measures_wire_size() {
+        let mut req = mock::http_request(Post, "/upload");
+        req.body = "hello".to_string();
+
+        // POST /upload HTTP/1.1\r\n, Host: localhost:3000\r\n, \r\n, hello
+        assert_eq!(wire_size(&req), 23 + 22 + 2 + 5);
+    }
+}
@@ -0,0 +1,122 @@
+//! Exposes the `QueryLimit` middleware, which rejects requests with
+//! overly large query strings.
+
+use http::status::BadRequest;
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue, Unwind};
+
+/// `Middleware` which answers requests with more than a maximum number of
+/// query parameters, or optionally a longer query string, with a
+/// `400 Bad Request`.
+///
+/// This stops `Middleware` further down the chain from parsing very
+/// large queries, and should be linked early:
+///
+/// ```ignore
+/// server.chain.link(QueryLimit::new(50).max_length(2048));
+/// ```
+#[deriving(Clone)]
+pub struct QueryLimit {
+    max_params: uint,
+    max_length: Option<uint>
+}
+
+impl QueryLimit {
+    /// Create a new `QueryLimit` allowing at most `max_params` query parameters.
+    pub fn new(max_params: uint) -> QueryLimit {
+        QueryLimit { max_params: max_params, max_length: None }
+    }
+
+    /// Also reject queries longer than `max_length` bytes.
+    pub fn max_length(mut self, max_length: uint) -> QueryLimit {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    // Pairs are counted on the raw query, without decoding any of them,
+    // and counting stops as soon as there are too many. Empty pairs, as
+    // in `a=1&&b=2`, are skipped like the parser does.
+    fn allows(&self, query: &str) -> bool {
+        match self.max_length {
+            Some(max) if query.len() > max => return false,
+            _ => ()
+        }
+        query.split('&').filter(|pair| !pair.is_empty()).skip(self.max_params).next().is_none()
+    }
+}
+
+impl Middleware for QueryLimit {
+    fn enter(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        let allowed = match req.url.query {
+            Some(ref query) => self.allows(query.as_slice()),
+            None => true
+        };
+
+        if allowed {
+            Continue
+        } else {
+            res.serve(BadRequest, "Bad Request");
+            Unwind
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::method::Get;
+    use http::status::BadRequest;
+
+    use super::QueryLimit;
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::middleware::{Status, Unwind, FromFn};
+    use HttpStatus = http::status::Status;
+
+    fn handled(_: &mut Request, res: &mut Response) -> Status {
+        res.serve(::http::status::Ok, "ok");
+        Unwind
+    }
+
+    fn status(limit: QueryLimit, url: &str) -> Option<HttpStatus> {
+        let mut chain: StackChain = Chain::new();
+        chain.link(limit);
+        chain.link(FromFn::new(handled));
+
+        let mut res = mock::response();
+        let _ = chain.dispatch(&mut mock::request(Get, url), &mut res);
+        res.status
+    }
+
+    #[test]
+    fn passes_acceptable_query() {
+        assert_eq!(status(QueryLimit::new(2), "http://localhost/search?q=iron&page=2"),
+                   Some(::http::status::Ok));
+        assert_eq!(status(QueryLimit::new(2), "http://localhost/search"),
+                   Some(::http::status::Ok));
+    }
+
+    #[test]
+    fn rejects_too_many_params() {
+        assert_eq!(status(QueryLimit::new(2), "http://localhost/search?a=1&b=2&c=3"),
+                   Some(BadRequest));
+    }
+
+    #[test]
+    fn counts_raw_pairs() {
+        assert_eq!(status(QueryLimit::new(2), "http://localhost/search?a=%26&&b=2&"),
+                   Some(::http::status::Ok));
+        assert_eq!(status(QueryLimit::new(2), "http://localhost/search?a&b&c"),
+                   Some(BadRequest));
+    }
+
+    #[test]
+    fn rejects_too_long_query() {
+        assert_eq!(status(QueryLimit::new(10).max_length(8), "http://localhost/search?q=ironironiron"),
+                   Some(BadRequest));
+    }
+}
@@ -1,13 +1,25 @@
 //! Iron's HTTP Request representation and associated methods.
 
 use std::io::net::ip::SocketAddr;
+use std::ascii::StrAsciiExt;
+use std::cell::RefCell;
 use http::server::request::{AbsoluteUri, AbsolutePath};
 use http::headers::request::HeaderCollection;
+use http::headers::HeaderEnum;
 use http::method::Method;
-use url::Url;
+use http::headers::connection::{Close, Token};
+use url::{Url, percent_decode, form_urlencoded};
+use serialize::Decodable;
+use serialize::base64::FromBase64;
 pub use HttpRequest = http::server::request::Request;
 
 use super::alloy::Alloy;
+use super::decode::{decode, FormDecoder, DecodeError};
+use super::protocol;
+
+// The headers defined as hop-by-hop by RFC 2616, section 13.5.1.
+static HOP_BY_HOP: &'static [&'static str] = &["connection", "keep-alive", "proxy-authenticate",
+    "proxy-authorization", "te", "trailers", "transfer-encoding", "upgrade"];
 
 /// The `Request` given to all `Middleware`.
 ///
@@ -19,11 +31,36 @@ pub struct Request {
     ///
     /// See `servo/rust-url`'s documentation for more information.  
     /// Useful methods include `Url::host`, `Url::domain` and `Url::query_pairs`.
+    ///
+    /// The url is parsed by `rust-url` following the URL Standard, so
+    /// dot-segments are already resolved, empty segments are kept, and
+    /// IPv6 hosts and userinfo in absolute-form requests are understood.
+    /// `Middleware` should use it instead of parsing the request URI.
     pub url: Url,
 
+    /// The percent-decoded segments of the requested path.
+    ///
+    /// Paths are decoded once, when the request is created, so that
+    /// `Middleware` can match them against literal patterns; `url` keeps
+    /// the path as it was sent, for logging.
+    ///
+    /// Each segment is decoded separately, so an encoded slash (`%2F`)
+    /// decodes to a `/` inside its segment and never splits it in two.
+    /// Matching segment by segment therefore treats it as a literal
+    /// character, not a path separator.
+    pub path: Vec<String>,
+
     /// The originating address of the request.
     pub remote_addr: Option<SocketAddr>,
 
+    /// The local address the request was received on.
+    ///
+    /// This is the local address of the connection, as accepted by the
+    /// server, so a server listening on `0.0.0.0` gives the address of the
+    /// interface the client connected to. Together with the scheme of
+    /// `url` it can be used to build absolute URLs.
+    pub local_addr: SocketAddr,
+
     /// The request headers.
     pub headers: Box<HeaderCollection>,
 
@@ -34,25 +71,62 @@ pub struct Request {
     pub method: Method,
 
     /// Storage for data passed between middleware.
-    pub alloy: Alloy
+    pub alloy: Alloy,
+
+    /// Storage for data kept across all requests on the same connection.
+    ///
+    /// On a keep-alive connection, values stored here by one request are
+    /// seen by the next. They are dropped when the connection closes.
+    pub connection: Alloy,
+
+    // The size of the request on the wire, see `bytes_received`.
+    received: uint,
+
+    // The request headers the response depends on, see `vary_on`. Kept
+    // in a `RefCell` so that helpers which only read the request can
+    // record the headers they read.
+    vary: RefCell<Vec<String>>
+}
+
+/// Why `Request::body_string` could not give the body.
+#[deriving(Clone, PartialEq, Show)]
+pub enum BodyError {
+    /// The body is longer than the allowed number of bytes, given here.
+    TooLarge(uint)
+}
+
+/// The credentials of a request, from its `Authorization` header.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Authorization {
+    /// A user name and password, sent with the `Basic` scheme.
+    Basic {
+        /// The user name.
+        user: String,
+        /// The password.
+        pass: String
+    },
+
+    /// A token sent with the `Bearer` scheme.
+    Bearer(String),
+
+    /// Credentials in any other scheme, or in a `Basic` or `Bearer` form
+    /// which could not be parsed, as the scheme and everything after it.
+    Other {
+        /// The scheme as sent, such as `Digest`.
+        scheme: String,
+        /// The rest of the header.
+        token: String
+    }
 }
 
 impl Request {
     /// Create a request from an HttpRequest.
     ///
-    /// This constructor consumes the HttpRequest.
-    pub fn from_http(req: HttpRequest) -> Option<Request> {
-        match req.request_uri {
-            AbsoluteUri(url) => {
-                Some(Request {
-                    url: url,
-                    remote_addr: req.remote_addr,
-                    headers: req.headers,
-                    body: req.body,
-                    method: req.method,
-                    alloy: Alloy::new()
-                })
-            },
+    /// This constructor consumes the HttpRequest. `local_addr` is the local
+    /// address of the connection the request arrived on.
+    pub fn from_http(req: HttpRequest, local_addr: SocketAddr) -> Option<Request> {
+        let url = match req.request_uri {
+            AbsoluteUri(url) => url,
             AbsolutePath(path) => {
                 // Attempt to prepend the Host header (mandatory in HTTP/1.1)
                 // XXX: HTTPS incompatible, update when switching to Teepee.
@@ -61,21 +135,489 @@ impl Request {
                     None => return None
                 };
 
-                let url = match Url::parse(url_string.as_slice()) {
+                match Url::parse(url_string.as_slice()) {
                     Ok(url) => url,
                     Err(_) => return None // Very unlikely.
-                };
+                }
+            },
+            _ => return None
+        };
+
+        let received = protocol::wire_size(&req);
+
+        Some(Request {
+            path: decode_path(&url),
+            url: url,
+            remote_addr: req.remote_addr,
+            local_addr: local_addr,
+            headers: req.headers,
+            body: req.body,
+            method: req.method,
+            alloy: Alloy::new(),
+            connection: Alloy::new(),
+            received: received,
+            vary: RefCell::new(vec![])
+        })
+    }
+
+    /// Every request header as a `(name, value)` pair.
+    ///
+    /// The parser merges repeated headers and does not keep their
+    /// original order, so the pairs come in the order of `headers`
+    /// with at most one pair per header name.
+    pub fn header_pairs(&self) -> Vec<(String, String)> {
+        self.headers.iter().map(|header| (header.header_name(), header.header_value())).collect()
+    }
+
+    /// The header names listed by the request's `Connection` header, which
+    /// only concern this connection, lowercased.
+    ///
+    /// `close` is an option rather than a header name, and is left out.
+    pub fn connection_tokens(&self) -> Vec<String> {
+        match self.headers.connection {
+            Some(ref options) => options.iter().filter_map(|option| match *option {
+                Token(ref token) => Some(token.as_slice().to_ascii_lower()),
+                Close => None
+            }).collect(),
+            None => vec![]
+        }
+    }
+
+    /// The request headers which should be forwarded by a proxy, as
+    /// `(name, value)` pairs in the order of `header_pairs`.
+    ///
+    /// This leaves out the headers named by `connection_tokens`, along
+    /// with `Connection` itself and the other hop-by-hop headers, which
+    /// only concern the connection to the client.
+    pub fn end_to_end_headers(&self) -> Vec<(String, String)> {
+        let tokens = self.connection_tokens();
+        self.header_pairs().move_iter().filter(|&(ref name, _)| {
+            let name = name.as_slice().to_ascii_lower();
+            !HOP_BY_HOP.contains(&name.as_slice()) && !tokens.contains(&name)
+        }).collect()
+    }
+
+    /// The credentials sent in the `Authorization` header, if any.
+    ///
+    /// Schemes are matched case-insensitively. `Basic` credentials which
+    /// are not valid base64 of UTF-8 text containing a `:`, and empty
+    /// `Bearer` tokens, are given as `Other`.
+    pub fn authorization(&self) -> Option<Authorization> {
+        let header = self.header_pairs().move_iter()
+            .find(|&(ref name, _)| name.as_slice().eq_ignore_ascii_case("Authorization"));
+        match header {
+            Some((_, value)) => parse_authorization(value.as_slice()),
+            None => None
+        }
+    }
+
+    /// The number of bytes received for this request, including its
+    /// request line and headers as well as the body.
+    ///
+    /// This is measured from the parsed request, see `protocol::wire_size`.
+    pub fn bytes_received(&self) -> uint {
+        self.received
+    }
+
+    /// The whole body as a `String`, if it is at most `max_bytes` long.
+    ///
+    /// rust-http answers a request whose body is not valid UTF-8 with
+    /// `400 Bad Request` before the `Chain` runs, so the body is always
+    /// valid UTF-8 here.
+    pub fn body_string(&self, max_bytes: uint) -> Result<String, BodyError> {
+        if self.body.len() > max_bytes {
+            Err(TooLarge(max_bytes))
+        } else {
+            Ok(self.body.clone())
+        }
+    }
+
+    /// Decode the query string into a `T`, usually a struct deriving
+    /// `Decodable`.
+    ///
+    /// A request without a query decodes like an empty one. The error
+    /// describes what is wrong with the query, and is the client's fault:
+    ///
+    /// ```ignore
+    /// #[deriving(Decodable)]
+    /// struct Search { q: String, page: Option<uint> }
+    ///
+    /// let search = match req.decode_query::<Search>() {
+    ///     Ok(search) => search,
+    ///     Err(e) => {
+    ///         req.alloy.insert(ErrorStatus(BadRequest));
+    ///         return Error(box e as Box<Show>);
+    ///     }
+    /// };
+    /// ```
+    pub fn decode_query<T: Decodable<FormDecoder, DecodeError>>(&self) -> Result<T, DecodeError> {
+        let query = self.url.query.clone().unwrap_or(String::new());
+        decode(form_urlencoded::parse(query.as_bytes()))
+    }
+
+    /// Decode the `application/x-www-form-urlencoded` body into a `T`, as
+    /// `decode_query` decodes the query string.
+    pub fn decode_form<T: Decodable<FormDecoder, DecodeError>>(&self) -> Result<T, DecodeError> {
+        decode(form_urlencoded::parse(self.body.as_bytes()))
+    }
+
+    /// The charsets accepted by the client with their q-values, in the
+    /// order of its `Accept-Charset` header.
+    ///
+    /// A request without the header accepts anything, as `*` with a
+    /// q-value of 1. Entries whose q-value is not a number from 0 to 1
+    /// are skipped. This records that the response varies on
+    /// `Accept-Charset`, see `vary_on`.
+    pub fn accept_charset(&self) -> Vec<(String, f32)> {
+        self.vary_on("Accept-Charset");
+        let header = self.header_pairs().move_iter()
+            .find(|&(ref name, _)| name.as_slice().eq_ignore_ascii_case("Accept-Charset"));
+
+        match header {
+            Some((_, value)) => value.as_slice().split(',').filter_map(parse_quality).collect(),
+            None => vec![("*".to_string(), 1.0)]
+        }
+    }
+
+    /// The charset out of `supported` the client prefers, if it accepts any.
+    ///
+    /// Ties are broken by the order of `supported`. Like `accept_charset`,
+    /// this records that the response varies on `Accept-Charset`.
+    pub fn preferred_charset(&self, supported: &[&str]) -> Option<String> {
+        let accepted = self.accept_charset();
+        let quality = |charset: &str| {
+            let exact = accepted.iter().find(|&&(ref name, _)| name.as_slice().eq_ignore_ascii_case(charset));
+            let any = accepted.iter().find(|&&(ref name, _)| name.as_slice() == "*");
+            match exact.or(any) {
+                Some(&(_, q)) => q,
+                None => 0.0
+            }
+        };
+
+        let mut best: Option<(&str, f32)> = None;
+        for charset in supported.iter() {
+            let q = quality(*charset);
+            match best {
+                Some((_, best_q)) if best_q >= q => (),
+                _ if q > 0.0 => best = Some((*charset, q)),
+                _ => ()
+            }
+        }
+        best.map(|(charset, _)| charset.to_string())
+    }
+
+    /// Record that the response depends on the request header `name`,
+    /// for the `Vary` middleware to announce in the response.
+    pub fn vary_on(&self, name: &str) {
+        let mut names = self.vary.borrow_mut();
+        if !names.iter().any(|n| n.as_slice().eq_ignore_ascii_case(name)) {
+            names.push(name.to_string());
+        }
+    }
+
+    /// The request headers recorded with `vary_on`, in the order they
+    /// were first recorded.
+    pub fn varies_on(&self) -> Vec<String> {
+        self.vary.borrow().clone()
+    }
+
+    /// Whether the request was made over a secure connection.
+    ///
+    /// This is determined by the scheme of the request's `url`.
+    pub fn is_secure(&self) -> bool {
+        self.url.scheme.as_slice() == "https"
+    }
+}
+
+// Parse the value of an `Authorization` header.
+fn parse_authorization(value: &str) -> Option<Authorization> {
+    let value = value.trim();
+    if value.is_empty() { return None }
+
+    let (scheme, token) = match value.find(' ') {
+        Some(i) => (value.slice_to(i), value.slice_from(i + 1).trim()),
+        None => (value, "")
+    };
+    let other = || Some(Other { scheme: scheme.to_string(), token: token.to_string() });
 
-                Some(Request {
-                    url: url,
-                    remote_addr: req.remote_addr,
-                    headers: req.headers,
-                    body: req.body,
-                    method: req.method,
-                    alloy: Alloy::new()
-                })
+    if scheme.eq_ignore_ascii_case("Basic") {
+        let decoded = match token.from_base64() {
+            Ok(bytes) => String::from_utf8(bytes).ok(),
+            Err(_) => None
+        };
+        match decoded {
+            Some(credentials) => match credentials.as_slice().find(':') {
+                Some(i) => Some(Basic {
+                    user: credentials.as_slice().slice_to(i).to_string(),
+                    pass: credentials.as_slice().slice_from(i + 1).to_string()
+                }),
+                None => other()
             },
-            _ => None
+            None => other()
         }
+    } else if scheme.eq_ignore_ascii_case("Bearer") && !token.is_empty() {
+        Some(Bearer(token.to_string()))
+    } else {
+        other()
+    }
+}
+
+// Parse one entry of a header like `utf-8;q=0.5` into its value and q-value.
+// A q-value which isn't a number from 0 to 1 makes the entry invalid.
+fn parse_quality(entry: &str) -> Option<(String, f32)> {
+    let mut parts = entry.split(';').map(|part| part.trim());
+    let value = match parts.next() {
+        Some(value) if !value.is_empty() => value.to_string(),
+        _ => return None
+    };
+
+    let mut quality = 1.0;
+    for param in parts {
+        let (name, q) = match param.find('=') {
+            Some(i) => (param.slice_to(i).trim(), param.slice_from(i + 1).trim()),
+            None => continue
+        };
+        if name.eq_ignore_ascii_case("q") {
+            quality = match from_str::<f32>(q) {
+                Some(q) if q >= 0.0 && q <= 1.0 => q,
+                _ => return None
+            };
+        }
+    }
+    Some((value, quality))
+}
+
+/// Percent-decode each segment of the path of `url`.
+///
+/// Invalid UTF-8 in a decoded segment is replaced, rather than
+/// failing the request.
+pub fn decode_path(url: &Url) -> Vec<String> {
+    match url.path() {
+        Some(path) => path.iter().map(|segment| {
+            String::from_utf8_lossy(percent_decode(segment.as_bytes()).as_slice()).into_string()
+        }).collect(),
+        None => vec![]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::net::ip::{SocketAddr, Ipv4Addr};
+    use http::method::{Get, Post};
+    use http::headers::connection::{Close, Token};
+
+    use super::{Request, TooLarge, Authorization, Basic, Bearer, Other};
+    use super::super::mock;
+    use super::super::decode::{MissingField, BadValue};
+
+    #[test]
+    fn stores_local_addr() {
+        let local = SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 3000 };
+        let req = Request::from_http(mock::http_request(Get, "/"), local).unwrap();
+        assert_eq!(req.local_addr.port, 3000);
+        assert_eq!(req.local_addr, local);
+    }
+
+    #[test]
+    fn lists_all_headers() {
+        let mut req = mock::request(Get, "http://localhost/");
+        req.headers.user_agent = Some("curl".to_string());
+        let _ = req.headers.extensions.insert("X-Forwarded-For".to_string(), "10.0.0.1".to_string());
+
+        let pairs = req.header_pairs();
+        assert!(pairs.contains(&("User-Agent".to_string(), "curl".to_string())));
+        assert!(pairs.contains(&("X-Forwarded-For".to_string(), "10.0.0.1".to_string())));
+        assert_eq!(pairs.len(), 2);
+    }
+
+    #[test]
+    fn counts_bytes_received() {
+        let mut http_req = mock::http_request(Get, "/");
+        http_req.body = "hello".to_string();
+        let local = SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 3000 };
+        let req = Request::from_http(http_req, local).unwrap();
+
+        // GET / HTTP/1.1\r\n, Host: localhost:3000\r\n, \r\n, hello
+        assert_eq!(req.bytes_received(), 16 + 22 + 2 + 5);
+    }
+
+    #[test]
+    fn reads_body_string() {
+        let mut req = mock::request(Get, "http://localhost/");
+        req.body = "café".to_string();
+        assert_eq!(req.body_string(5), Ok("café".to_string()));
+
+        // A replacement character sent by the client is kept as is.
+        req.body = "caf\uFFFD".to_string();
+        assert_eq!(req.body_string(1024), Ok("caf\uFFFD".to_string()));
+
+        req.body = "a longer body".to_string();
+        assert_eq!(req.body_string(4), Err(TooLarge(4)));
+    }
+
+    fn authorized(value: &str) -> Option<Authorization> {
+        let mut req = mock::request(Get, "http://localhost/");
+        let _ = req.headers.extensions.insert("Authorization".to_string(), value.to_string());
+        req.authorization()
+    }
+
+    #[test]
+    fn parses_basic_authorization() {
+        // "Aladdin:open sesame"
+        assert_eq!(authorized("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="),
+                   Some(Basic { user: "Aladdin".to_string(), pass: "open sesame".to_string() }));
+        assert_eq!(authorized("basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="),
+                   Some(Basic { user: "Aladdin".to_string(), pass: "open sesame".to_string() }));
+    }
+
+    #[test]
+    fn parses_bearer_authorization() {
+        assert_eq!(authorized("Bearer mF_9.B5f-4.1JqM"), Some(Bearer("mF_9.B5f-4.1JqM".to_string())));
+    }
+
+    #[test]
+    fn keeps_malformed_and_unknown_authorization() {
+        assert_eq!(authorized("Basic !!!"),
+                   Some(Other { scheme: "Basic".to_string(), token: "!!!".to_string() }));
+        // "Aladdin", without a password.
+        assert_eq!(authorized("Basic QWxhZGRpbg=="),
+                   Some(Other { scheme: "Basic".to_string(), token: "QWxhZGRpbg==".to_string() }));
+        assert_eq!(authorized("Digest username=\"Mufasa\""),
+                   Some(Other { scheme: "Digest".to_string(), token: "username=\"Mufasa\"".to_string() }));
+        assert_eq!(authorized(""), None);
+        assert_eq!(mock::request(Get, "http://localhost/").authorization(), None);
+    }
+
+    #[test]
+    fn lists_connection_tokens() {
+        let mut req = mock::request(Get, "http://localhost/");
+        req.headers.connection = Some(vec![Token("X-Foo".to_string()), Close]);
+        assert_eq!(req.connection_tokens(), vec!["x-foo".to_string()]);
+    }
+
+    #[test]
+    fn excludes_connection_headers_from_forwarding() {
+        let mut req = mock::request(Get, "http://localhost/");
+        req.headers.connection = Some(vec![Token("x-foo".to_string())]);
+        let _ = req.headers.extensions.insert("X-Foo".to_string(), "hop".to_string());
+        let _ = req.headers.extensions.insert("X-Bar".to_string(), "end".to_string());
+        let _ = req.headers.extensions.insert("Keep-Alive".to_string(), "timeout=5".to_string());
+
+        assert_eq!(req.end_to_end_headers(), vec![("X-Bar".to_string(), "end".to_string())]);
+    }
+
+    #[deriving(Decodable, PartialEq, Show)]
+    struct Search {
+        q: String,
+        page: Option<uint>,
+        tag: Vec<String>
+    }
+
+    #[test]
+    fn decodes_query() {
+        let req = mock::request(Get, "http://localhost/search?q=iron&page=2&tag=web&tag=rust");
+        assert_eq!(req.decode_query::<Search>(), Ok(Search {
+            q: "iron".to_string(),
+            page: Some(2),
+            tag: vec!["web".to_string(), "rust".to_string()]
+        }));
+
+        let req = mock::request(Get, "http://localhost/search?q=iron");
+        assert_eq!(req.decode_query::<Search>(),
+                   Ok(Search { q: "iron".to_string(), page: None, tag: vec![] }));
+    }
+
+    #[test]
+    fn decodes_form() {
+        let mut req = mock::request(Post, "http://localhost/search");
+        req.body = "q=caf%C3%A9&page=1".to_string();
+        assert_eq!(req.decode_form::<Search>(),
+                   Ok(Search { q: "café".to_string(), page: Some(1), tag: vec![] }));
+    }
+
+    #[test]
+    fn fails_to_decode_bad_query() {
+        let req = mock::request(Get, "http://localhost/search?page=2");
+        assert_eq!(req.decode_query::<Search>(), Err(MissingField("q".to_string())));
+
+        let req = mock::request(Get, "http://localhost/search?q=iron&page=two");
+        assert_eq!(req.decode_query::<Search>(),
+                   Err(BadValue("page".to_string(), "two".to_string())));
+    }
+
+    #[test]
+    fn parses_accept_charset() {
+        let mut req = mock::request(Get, "http://localhost/");
+        let _ = req.headers.extensions.insert("Accept-Charset".to_string(),
+                                              "utf-8, iso-8859-1;q=0.5".to_string());
+
+        assert_eq!(req.accept_charset(),
+                   vec![("utf-8".to_string(), 1.0), ("iso-8859-1".to_string(), 0.5)]);
+        assert_eq!(req.preferred_charset(["iso-8859-1", "utf-8"]), Some("utf-8".to_string()));
+        assert_eq!(req.preferred_charset(["iso-8859-1", "shift_jis"]), Some("iso-8859-1".to_string()));
+        assert_eq!(req.preferred_charset(["shift_jis"]), None);
+        assert_eq!(req.varies_on(), vec!["Accept-Charset".to_string()]);
+    }
+
+    #[test]
+    fn accepts_any_charset_by_default() {
+        let req = mock::request(Get, "http://localhost/");
+        assert_eq!(req.accept_charset(), vec![("*".to_string(), 1.0)]);
+        assert_eq!(req.preferred_charset(["shift_jis", "utf-8"]), Some("shift_jis".to_string()));
+
+        let mut req = mock::request(Get, "http://localhost/");
+        let _ = req.headers.extensions.insert("Accept-Charset".to_string(), "utf-8;q=0.1, *;q=0.8".to_string());
+        assert_eq!(req.preferred_charset(["utf-8", "shift_jis"]), Some("shift_jis".to_string()));
+    }
+
+    #[test]
+    fn reads_q_values_in_any_case_and_skips_those_out_of_range() {
+        let mut req = mock::request(Get, "http://localhost/");
+        let _ = req.headers.extensions.insert("Accept-Charset".to_string(),
+                                              "utf-8;Q=0.5, latin1;q=2, ascii;q=-1, koi8-r;q=abc".to_string());
+        assert_eq!(req.accept_charset(), vec![("utf-8".to_string(), 0.5)]);
+    }
+
+    #[test]
+    fn decodes_path() {
+        let req = mock::request(Get, "http://localhost/caf%C3%A9/menu");
+        assert_eq!(req.path, vec!["café".to_string(), "menu".to_string()]);
+        assert_eq!(req.url.serialize_path().unwrap().as_slice(), "/caf%C3%A9/menu");
+    }
+
+    fn parsed(path: &str) -> Request {
+        let local = SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 3000 };
+        Request::from_http(mock::http_request(Get, path), local).unwrap()
+    }
+
+    #[test]
+    fn keeps_empty_segments() {
+        let req = parsed("//double//slashes");
+        assert_eq!(req.path, vec!["".to_string(), "double".to_string(),
+                                  "".to_string(), "slashes".to_string()]);
+        assert_eq!(req.url.domain(), Some("localhost"));
+    }
+
+    #[test]
+    fn resolves_dot_segments() {
+        let req = parsed("/a/./b/../c?x=1");
+        assert_eq!(req.path, vec!["a".to_string(), "c".to_string()]);
+        assert_eq!(req.url.query, Some("x=1".to_string()));
+    }
+
+    #[test]
+    fn parses_absolute_form() {
+        let req = mock::request(Get, "http://user:secret@[::1]:8080/status");
+        assert_eq!(req.url.domain(), None);
+        assert_eq!(req.url.port(), Some(8080));
+        assert_eq!(req.url.username(), Some("user"));
+        assert_eq!(req.url.password(), Some("secret"));
+        assert_eq!(req.path, vec!["status".to_string()]);
+    }
+
+    #[test]
+    fn keeps_encoded_slash_in_segment() {
+        let req = mock::request(Get, "http://localhost/a%2Fb/c");
+        assert_eq!(req.path, vec!["a/b".to_string(), "c".to_string()]);
     }
 }
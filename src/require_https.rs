@@ -0,0 +1,154 @@
+//! Exposes the `RequireHttps` middleware, which redirects plain
+//! HTTP requests to their HTTPS equivalent.
+
+use http::status::{MovedPermanently, BadRequest};
+use url::Url;
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue, Unwind};
+
+/// `Middleware` which redirects every insecure request to the same
+/// host, path and query over `https://` with a `301 Moved Permanently`.
+///
+/// Secure requests, and requests to any of the exempted paths, pass
+/// through untouched. An insecure request with no host to redirect to is
+/// answered with `400 Bad Request` rather than being let through:
+///
+/// ```ignore
+/// server.chain.link(RequireHttps::new().exempt("/health"));
+/// ```
+#[deriving(Clone)]
+pub struct RequireHttps {
+    exempt: Vec<String>
+}
+
+impl RequireHttps {
+    /// Create a new `RequireHttps` with no exempt paths.
+    pub fn new() -> RequireHttps {
+        RequireHttps { exempt: vec![] }
+    }
+
+    /// Exempt `path` from being redirected, for instance for health checks
+    /// which are made over plain HTTP.
+    pub fn exempt(mut self, path: &str) -> RequireHttps {
+        self.exempt.push(path.to_string());
+        self
+    }
+
+    fn is_exempt(&self, req: &Request) -> bool {
+        match req.url.serialize_path() {
+            Some(path) => self.exempt.iter().any(|p| p.as_slice() == path.as_slice()),
+            None => false
+        }
+    }
+}
+
+// The https:// equivalent of `url`, dropping any explicit port since
+// it refers to the insecure listener. IP literals are kept as they were
+// written, IPv6 ones in brackets.
+fn secure_url(url: &Url) -> Option<Url> {
+    let host = match url.serialize_host() {
+        Some(host) => host,
+        None => return None
+    };
+
+    let path = url.serialize_path().unwrap_or("/".to_string());
+    let target = match url.query {
+        Some(ref query) => format!("https://{}{}?{}", host, path, query),
+        None => format!("https://{}{}", host, path)
+    };
+
+    Url::parse(target.as_slice()).ok()
+}
+
+impl Middleware for RequireHttps {
+    fn enter(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        if req.is_secure() || self.is_exempt(req) {
+            return Continue;
+        }
+
+        match secure_url(&req.url) {
+            Some(url) => {
+                res.headers.location = Some(url);
+                res.serve(MovedPermanently, "");
+                Unwind
+            },
+            // Nothing sensible to redirect to, but the request must not
+            // be served insecurely either.
+            None => {
+                res.serve(BadRequest, "");
+                Unwind
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::method::Get;
+    use http::status::MovedPermanently;
+
+    use super::RequireHttps;
+    use super::super::mock;
+    use super::super::middleware::{Middleware, Continue, Unwind};
+
+    #[test]
+    fn redirects_to_https() {
+        let mut req = mock::request(Get, "http://example.com:8080/a/b?c=d");
+        let mut res = mock::response();
+
+        match RequireHttps::new().enter(&mut req, &mut res) {
+            Unwind => (),
+            _ => fail!("RequireHttps did not unwind on an insecure request.")
+        }
+
+        assert_eq!(res.status, Some(MovedPermanently));
+        assert_eq!(res.headers.location.unwrap().serialize().as_slice(),
+                   "https://example.com/a/b?c=d");
+    }
+
+    #[test]
+    fn redirects_ip_literal_hosts() {
+        let mut req = mock::request(Get, "http://[::1]:8080/a");
+        let mut res = mock::response();
+
+        match RequireHttps::new().enter(&mut req, &mut res) {
+            Unwind => (),
+            _ => fail!("RequireHttps did not unwind on an insecure IPv6 request.")
+        }
+
+        assert_eq!(res.status, Some(MovedPermanently));
+        assert_eq!(res.headers.location.unwrap().serialize().as_slice(), "https://[::1]/a");
+
+        let mut req = mock::request(Get, "http://127.0.0.1/");
+        let mut res = mock::response();
+        let _ = RequireHttps::new().enter(&mut req, &mut res);
+        assert_eq!(res.headers.location.unwrap().serialize().as_slice(), "https://127.0.0.1/");
+    }
+
+    #[test]
+    fn passes_secure_requests() {
+        let mut req = mock::request(Get, "https://example.com/");
+        let mut res = mock::response();
+
+        match RequireHttps::new().enter(&mut req, &mut res) {
+            Continue => (),
+            _ => fail!("RequireHttps did not continue on a secure request.")
+        }
+        assert!(res.status.is_none());
+    }
+
+    #[test]
+    fn passes_exempt_paths() {
+        let mut req = mock::request(Get, "http://example.com/health");
+        let mut res = mock::response();
+
+        match RequireHttps::new().exempt("/health").enter(&mut req, &mut res) {
+            Continue => (),
+            _ => fail!("RequireHttps did not continue on an exempt path.")
+        }
+        assert!(res.status.is_none());
+        assert!(res.headers.location.is_none());
+    }
+}
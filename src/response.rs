@@ -1,17 +1,32 @@
 //! Iron's HTTP Response representation and associated methods.
 
-use std::io::{IoResult, File, MemReader};
+use std::io::{IoResult, IoError, File, MemReader, EndOfFile, BrokenPipe, standard_error};
 use std::path::BytesContainer;
+use std::ascii::StrAsciiExt;
+use std::mem;
+use std::num::from_u16;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUint, AtomicBool, SeqCst};
 
-use http::status::{Status, InternalServerError, NotFound};
+use time::{Tm, Timespec, at_utc};
+
+use http::status::{Status, InternalServerError, NotFound, NotModified, UnregisteredStatus};
 use OkStatus = http::status::Ok;
 use http::headers::response::HeaderCollection;
 use http::headers::content_type::MediaType;
-
-pub use HttpResponse = http::server::response::ResponseWriter;
+use http::headers::HeaderEnum;
+use http::headers::connection::Close;
+use http::headers::transfer_encoding::Chunked;
+use http::headers::accept_ranges::NoAcceptableRanges;
+use http::method::Head;
+use url::Url;
 
 use contenttype::get_content_type;
 
+use super::request::{HttpRequest, Request};
+use super::middleware;
+use super::sniff::sniff;
+
 /// The response representation given to `Middleware`
 pub struct Response {
     /// The body of the response.
@@ -26,25 +41,426 @@ pub struct Response {
     pub headers: Box<HeaderCollection>,
 
     /// The response status-code.
-    pub status: Option<Status>
+    pub status: Option<Status>,
+
+    // Whether `body` is fed by a `ResponseStream`.
+    streaming: bool,
+
+    // The number of bytes of chunked body flushed by the `ResponseStream`.
+    streamed: Arc<AtomicUint>,
+
+    // The trailer fields to send after a chunked body.
+    trailers: Vec<(String, String)>,
+
+    // The value of each `Set-Cookie` header, in the order they were set.
+    cookies: Vec<String>,
+
+    // The custom reason phrase for the status code it was set with.
+    reason: Option<(u16, String)>,
+
+    // Whether the request asked for the connection to be closed.
+    close_requested: bool
+}
+
+/// A value which can't be sent because it contains a CR or LF, which
+/// would let it end the line early and inject headers into the response.
+#[deriving(Clone, PartialEq, Show)]
+pub struct InvalidValue;
+
+/// A `Writer` which streams data to the client as it is flushed.
+///
+/// Obtained from `Response::stream`. Written data is buffered until
+/// `flush` is called, at which point it is handed to the server, which
+/// sends it as soon as the `Chain` has returned. The stream is closed, and
+/// the response completed, when the `ResponseStream` is dropped.
+///
+/// Once the response is being sent, at most `STREAM_BUFFER` flushed
+/// chunks wait for the client. Beyond that `flush` blocks until the client
+/// has taken one, so a slow client throttles the writer instead of its
+/// chunks piling up in memory. Chunks flushed before then, such as from
+/// within the `Middleware` itself, are all kept. Once the client is gone
+/// `flush` gives a `BrokenPipe` error.
+pub struct ResponseStream {
+    buffer: Vec<u8>,
+    sender: Sender<Vec<u8>>,
+    // One message for each chunk taken off the queue to be sent.
+    taken: Receiver<()>,
+    // The number of flushed chunks not yet known to be taken.
+    waiting: uint,
+    // Whether the response has started to be sent.
+    started: Arc<AtomicBool>,
+    streamed: Arc<AtomicUint>
+}
+
+/// The number of flushed chunks of a `ResponseStream` which may wait to be
+/// sent before `flush` blocks.
+pub static STREAM_BUFFER: uint = 4;
+
+fn broken_pipe() -> IoError {
+    IoError {
+        kind: BrokenPipe,
+        desc: "the response is no longer being sent",
+        detail: None
+    }
+}
+
+impl Writer for ResponseStream {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        self.buffer.push_all(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        if self.buffer.is_empty() { return Ok(()) }
+        let chunk = mem::replace(&mut self.buffer, vec![]);
+        // Each chunk is framed as "<hex length>\r\n<data>\r\n".
+        let framed = format!("{:x}", chunk.len()).len() + 2 + chunk.len() + 2;
+        let _ = self.streamed.fetch_add(framed, SeqCst);
+        try!(self.sender.send_opt(chunk).map_err(|_| broken_pipe()));
+        self.waiting += 1;
+
+        // Blocking before the response is sent would never end if the
+        // chain itself is flushing.
+        if !self.started.load(SeqCst) { return Ok(()) }
+        while self.waiting > STREAM_BUFFER {
+            try!(self.taken.recv_opt().map_err(|_| broken_pipe()));
+            self.waiting -= 1;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ResponseStream {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
 }
 
 impl Response {
-    /// Construct a Response from an HttpResponse reference
-    pub fn from_http(http_res: &mut HttpResponse) -> Response {
+    /// Construct an empty Response with no status.
+    pub fn new() -> Response {
         Response {
-            headers: http_res.headers.clone(),
+            headers: box HeaderCollection::new(),
             status: None, // Start with no response code.
-            body: box MemReader::new(vec![]) as Box<Reader>
+            body: box MemReader::new(vec![]) as Box<Reader>,
+            streaming: false,
+            streamed: Arc::new(AtomicUint::new(0)),
+            trailers: vec![],
+            cookies: vec![],
+            reason: None,
+            close_requested: false
+        }
+    }
+
+    /// Construct the Response to an HttpRequest, which remembers whether
+    /// the request asked for its connection to be closed.
+    pub fn from_http(http_req: &HttpRequest) -> Response {
+        Response {
+            close_requested: http_req.close_connection,
+            ..Response::new()
         }
     }
 
     /// Write the `Status` and data to the `Response`.
+    ///
+    /// Fails if the response is already being streamed.
     pub fn serve<S: BytesContainer>(&mut self, status: Status, body: S) {
+        if self.streaming { fail!("serve called on a streaming Response.") }
         self.status = Some(status);
         self.body = box MemReader::new(body.container_as_bytes().to_vec()) as Box<Reader>;
     }
 
+    /// Set the status, keeping the body, and give back the `Response` so
+    /// more calls can follow, such as `unwind`:
+    ///
+    /// ```ignore
+    /// if !allowed(req) {
+    ///     return res.with_status(Forbidden).unwind();
+    /// }
+    /// ```
+    pub fn with_status(&mut self, status: Status) -> &mut Response {
+        self.status = Some(status);
+        self
+    }
+
+    /// End a `Middleware`'s `enter` with this response: the same as
+    /// returning `Unwind`, at the end of a chain of calls on `Response`.
+    #[inline]
+    pub fn unwind(&self) -> middleware::Status {
+        middleware::Unwind
+    }
+
+    /// Set the status to `code` with a custom reason phrase, sent instead
+    /// of the standard one in the status line.
+    ///
+    /// A registered `code` is kept as its usual `Status`, so that the
+    /// response still compares equal to, say, `status::Ok`; the reason is
+    /// only sent while the status has that code.
+    ///
+    /// Gives `InvalidValue`, leaving the status unchanged, if `reason`
+    /// contains a CR or LF.
+    pub fn set_status_with_reason(&mut self, code: u16, reason: &str) -> Result<(), InvalidValue> {
+        if reason.contains_char('\r') || reason.contains_char('\n') {
+            return Err(InvalidValue);
+        }
+        let status = from_u16::<Status>(code).unwrap_or(UnregisteredStatus(code, reason.to_string()));
+        self.status = Some(status);
+        self.reason = Some((code, reason.to_string()));
+        Ok(())
+    }
+
+    /// Whether a header called `name` has been set, compared
+    /// case-insensitively.
+    pub fn has_header(&self, name: &str) -> bool {
+        (!self.cookies.is_empty() && name.eq_ignore_ascii_case("Set-Cookie")) ||
+            self.headers.iter().any(|header| header.header_name().as_slice().eq_ignore_ascii_case(name))
+    }
+
+    /// Every response header as a `(name, value)` pair, in the order of
+    /// `headers`, followed by one `Set-Cookie` pair for each cookie.
+    pub fn header_pairs(&self) -> Vec<(String, String)> {
+        let mut pairs: Vec<(String, String)> = self.headers.iter()
+            .map(|header| (header.header_name(), header.header_value())).collect();
+        pairs.extend(self.cookies.iter().map(|cookie| ("Set-Cookie".to_string(), cookie.clone())));
+        pairs
+    }
+
+    /// Add a `Set-Cookie` header with the value `cookie`, such as
+    /// `"theme=dark; Path=/"`.
+    ///
+    /// Unlike `set_header`, this never replaces an earlier value: every
+    /// cookie is sent in a `Set-Cookie` header of its own, as clients
+    /// can't split a combined one. Control characters are stripped as
+    /// for `set_header`.
+    pub fn set_cookie(&mut self, cookie: &str) {
+        self.cookies.push(strip_controls(cookie));
+    }
+
+    /// The value of every `Set-Cookie` header added by `set_cookie`.
+    pub fn cookies(&self) -> &[String] {
+        self.cookies.as_slice()
+    }
+
+    /// Set the header called `name` to `value`, replacing any header of
+    /// that name, compared case-insensitively.
+    ///
+    /// `Cache-Control`, `Content-Length`, `Content-Type` and `Location`
+    /// are set through their typed field on `headers`, so that they are
+    /// not sent twice; a value which doesn't parse for the last three is
+    /// dropped with a warning. Other headers with a typed field should be
+    /// set through it directly.
+    ///
+    /// Control characters other than tab are stripped from both, and a
+    /// warning logged, so that a value taken from untrusted input can't
+    /// end the header early and inject others into the response.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        let (name, value) = (strip_controls(name), strip_controls(value));
+
+        let existing: Vec<String> = self.headers.extensions.keys()
+            .filter(|key| key.as_slice().eq_ignore_ascii_case(name.as_slice()))
+            .map(|key| key.clone()).collect();
+        for key in existing.iter() {
+            let _ = self.headers.extensions.remove(key);
+        }
+
+        match name.as_slice().to_ascii_lower().as_slice() {
+            "cache-control" => self.headers.cache_control = Some(value),
+            "content-length" => match from_str::<uint>(value.as_slice().trim()) {
+                Some(length) => self.headers.content_length = Some(length),
+                None => warn!("Dropped invalid Content-Length: {}", value)
+            },
+            "content-type" => match parse_media_type(value.as_slice()) {
+                Some(media_type) => self.headers.content_type = Some(media_type),
+                None => warn!("Dropped invalid Content-Type: {}", value)
+            },
+            "location" => match Url::parse(value.as_slice()) {
+                Ok(url) => self.headers.location = Some(url),
+                Err(_) => warn!("Dropped invalid Location: {}", value)
+            },
+            _ => { let _ = self.headers.extensions.insert(name, value); }
+        }
+    }
+
+    /// Set the trailer field `name` to `value`, to be sent after the last
+    /// chunk of a streaming response.
+    ///
+    /// The field is also announced in the `Trailer` header, so it must be
+    /// set before the headers are written. Trailers, and the `Trailer`
+    /// header, are dropped for responses which are not sent chunked.
+    ///
+    /// Control characters other than tab are stripped from `value`, as by
+    /// `set_header`. Gives `InvalidValue`, setting nothing, if `name` is
+    /// not a valid header name.
+    pub fn set_trailer(&mut self, name: &str, value: &str) -> Result<(), InvalidValue> {
+        if name.is_empty() || !name.chars().all(is_token_char) {
+            return Err(InvalidValue);
+        }
+        let value = strip_controls(value);
+
+        match self.trailers.iter().position(|&(ref n, _)| n.as_slice().eq_ignore_ascii_case(name)) {
+            Some(i) => *self.trailers.get_mut(i) = (name.to_string(), value),
+            None => self.trailers.push((name.to_string(), value))
+        }
+
+        let names: Vec<&str> = self.trailers.iter().map(|&(ref n, _)| n.as_slice()).collect();
+        let announced = names.connect(", ");
+        self.set_header("Trailer", announced.as_slice());
+        Ok(())
+    }
+
+    /// Ask for the connection to be closed after this response, even if
+    /// it would otherwise be kept alive.
+    ///
+    /// This sends `Connection: close`, and the server closes the
+    /// connection once the response has been written.
+    pub fn set_connection_close(&mut self) {
+        self.headers.connection = Some(vec![Close]);
+    }
+
+    /// Whether `set_connection_close` has been called, or a `Connection:
+    /// close` header set otherwise.
+    pub fn is_connection_close(&self) -> bool {
+        match self.headers.connection {
+            Some(ref options) => options.iter().any(|option| *option == Close),
+            None => false
+        }
+    }
+
+    /// Whether the connection will be kept alive after this response.
+    ///
+    /// It is not if the request asked for it to be closed, which an
+    /// HTTP/1.0 request does unless it sends `Connection: keep-alive` and
+    /// an HTTP/1.1 request does by sending `Connection: close`, or if
+    /// `set_connection_close` was called. This can be read in `exit`.
+    pub fn will_keep_alive(&self) -> bool {
+        !self.close_requested && !self.is_connection_close()
+    }
+
+    /// Stream the body of the response.
+    ///
+    /// The returned `ResponseStream` can be moved to another task which
+    /// keeps writing after the `Middleware` has returned. Once the `Chain`
+    /// has returned, every `flush` of the stream is written to the client
+    /// as soon as it is made, which is what long-lived responses such as
+    /// event streams need.
+    ///
+    /// Nothing is sent until the `Chain` returns, so chunks flushed before
+    /// then are kept until it has. Only after that does `flush` block once
+    /// `STREAM_BUFFER` chunks are waiting. The response ends when the
+    /// stream is dropped, so it must not be kept in `Request::connection`,
+    /// which outlives the response; `Request::alloy` is emptied before the
+    /// response is sent.
+    ///
+    /// Unless a Content-Type has been set, the body is sent as plain text.
+    ///
+    /// Once a response is streaming its body cannot be reset, and since its
+    /// length is unknown it is sent with chunked transfer encoding. Unless
+    /// an `Accept-Ranges` header has been set, `Accept-Ranges: none` tells
+    /// clients not to ask for part of it.
+    pub fn stream(&mut self) -> ResponseStream {
+        let (sender, receiver) = channel();
+        let (taken_sender, taken) = channel();
+        let started = Arc::new(AtomicBool::new(false));
+        self.streaming = true;
+        if self.headers.accept_ranges.is_none() {
+            self.headers.accept_ranges = Some(NoAcceptableRanges);
+        }
+        self.status = Some(self.status.clone().unwrap_or(OkStatus));
+        self.body = box StreamReader {
+            receiver: receiver,
+            taken: taken_sender,
+            started: started.clone(),
+            chunk: vec![],
+            pos: 0
+        } as Box<Reader>;
+        ResponseStream {
+            buffer: vec![],
+            sender: sender,
+            taken: taken,
+            waiting: 0,
+            started: started,
+            streamed: self.streamed.clone()
+        }
+    }
+
+    /// Send the body read from `reader`, copying it to the client as it is
+    /// read instead of buffering all of it.
+    ///
+    /// With a `length`, the body is sent with that Content-Length and must
+    /// be exactly as long; without, it is sent with chunked transfer
+    /// encoding. As with `stream`, the body can't be reset afterwards, and
+    /// is sent as plain text unless a Content-Type has been set.
+    ///
+    /// An error reading from `reader` aborts the response: `write_to` fails
+    /// without ending the body, and the server logs the error and closes
+    /// the connection, so the client can tell that the body is cut short.
+    pub fn send_reader<R: Reader + 'static>(&mut self, reader: R, length: Option<uint>) {
+        self.streaming = true;
+        self.headers.content_length = length;
+        if self.headers.accept_ranges.is_none() {
+            self.headers.accept_ranges = Some(NoAcceptableRanges);
+        }
+        self.status = Some(self.status.clone().unwrap_or(OkStatus));
+        self.body = box CountingReader {
+            inner: reader,
+            streamed: self.streamed.clone(),
+            chunked: length.is_none()
+        } as Box<Reader>;
+    }
+
+    /// The number of bytes this response takes on the wire, including its
+    /// status line and headers as well as the body.
+    ///
+    /// This can be read in `exit`, before the response is written, so the
+    /// count is approximate. It includes the Content-Length the server will
+    /// add, measured by buffering the body, and its plain text default
+    /// Content-Type, though content sniffing may pick another. Headers the
+    /// server adds after the chain, such as `Connection`, are not counted,
+    /// and neither is the body, nor its headers, for a status such as 304
+    /// which allows none.
+    /// For a streaming response the count grows as chunks are flushed, or
+    /// as the body given to `send_reader` is read.
+    ///
+    /// An error reading the body is returned, and the body left failing
+    /// with it, so the response is still answered with a `500` as if the
+    /// body had not been read early.
+    pub fn bytes_sent(&mut self) -> IoResult<uint> {
+        let status_line = self.status_line().len();
+        let mut headers = self.headers.iter().fold(0, |total, header| {
+            total + header.header_name().len() + 2 + header.header_value().len() + 2
+        }) + 2;
+        headers += self.cookies.iter().fold(0, |total, cookie| {
+            total + "Set-Cookie: ".len() + cookie.len() + 2
+        });
+        if !self.allows_body() {
+            return Ok(status_line + headers);
+        }
+        if self.headers.content_type.is_none() {
+            headers += format!("Content-Type: {}\r\n", get_content_type("txt").unwrap()).len();
+        }
+
+        if self.streaming {
+            if self.headers.transfer_encoding.is_none() && self.headers.content_length.is_none() {
+                headers += "Transfer-Encoding: chunked\r\n".len();
+            }
+            return Ok(status_line + headers + self.streamed.load(SeqCst));
+        }
+
+        let body = match self.body.read_to_end() {
+            Ok(body) => body,
+            Err(e) => {
+                self.body = box FailedReader { error: e.clone() } as Box<Reader>;
+                return Err(e);
+            }
+        };
+        if self.headers.content_length.is_none() {
+            headers += format!("Content-Length: {}\r\n", body.len()).len();
+        }
+        let length = body.len();
+        self.body = box MemReader::new(body) as Box<Reader>;
+        Ok(status_line + headers + length)
+    }
+
     /// Serve the file located at `path`.
     ///
     /// This usually means a request has been handled, and `Middleware`
@@ -55,51 +471,344 @@ impl Response {
     /// `serve_file` will error if the file does not exist, the process
     /// does not have correct permissions, or it has other issues in reading
     /// from the file. `Middleware` should handle this gracefully.
+    ///
+    /// The `Last-Modified` header is set from the file's modification time.
+    /// `Range` requests are not supported yet, so `Accept-Ranges: none`
+    /// tells clients not to try to resume the download of a file.
     pub fn serve_file(&mut self, path: &Path) -> IoResult<()> {
         let file = try!(File::open(path));
+        self.headers.last_modified = Some(try!(modified_at(path)));
+        self.headers.accept_ranges = Some(NoAcceptableRanges);
         self.headers.content_type = path.extension_str().and_then(get_content_type);
         self.body = box file as Box<Reader>;
         self.status = Some(OkStatus);
         Ok(())
     }
 
-    // `write_back` is used to put all the data added to `self`
-    // back onto an `HttpResponse` so that it is sent back to the
-    // client.
+    /// Serve the file located at `path`, honoring the request's
+    /// `If-Modified-Since` header.
+    ///
+    /// If the file has not been modified since the time given by the
+    /// client, a `304 Not Modified` with an empty body is served instead
+    /// of the file. Otherwise this behaves exactly like `serve_file`.
+    ///
+    /// The header's date formats (including RFC 1123) are parsed when
+    /// the request is read, so this only compares timestamps.
+    pub fn serve_file_if_modified(&mut self, req: &Request, path: &Path) -> IoResult<()> {
+        let mtime = try!(modified_at(path));
+
+        match req.headers.if_modified_since {
+            Some(ref since) if !is_modified(&mtime, since) => {
+                self.headers.last_modified = Some(mtime);
+                self.serve(NotModified, "");
+                Ok(())
+            },
+            _ => self.serve_file(path)
+        }
+    }
+
+    /// Whether the copy the client has cached, as given by the conditional
+    /// headers of `req`, is still fresh, so a `304 Not Modified` can be
+    /// sent instead of this response.
+    ///
+    /// An `If-None-Match` header is compared against this response's
+    /// `ETag`, weakly, and takes precedence over `If-Modified-Since`,
+    /// which is compared against its `Last-Modified`. A request with
+    /// neither, or a response without the header compared, is not fresh.
+    pub fn is_fresh(&self, req: &Request) -> bool {
+        let find = |pairs: Vec<(String, String)>, name: &str| {
+            pairs.move_iter().find(|&(ref n, _)| n.as_slice().eq_ignore_ascii_case(name)).map(|(_, v)| v)
+        };
+
+        match find(req.header_pairs(), "If-None-Match") {
+            Some(tags) => match find(self.header_pairs(), "ETag") {
+                Some(etag) => tags.as_slice().split(',').any(|tag| {
+                    let tag = tag.trim();
+                    tag == "*" || weak_tag(tag) == weak_tag(etag.as_slice())
+                }),
+                None => false
+            },
+            None => match (&req.headers.if_modified_since, &self.headers.last_modified) {
+                (&Some(ref since), &Some(ref mtime)) => !is_modified(mtime, since),
+                _ => false
+            }
+        }
+    }
+
+    /// Whether this response's body is streamed, through `stream` or
+    /// `send_reader`.
+    pub fn is_streaming(&self) -> bool {
+        self.streaming
+    }
+
+    // `write_back` sends the response to the client, in answer to `req`,
+    // on the connection `w`.
     //
     // `write_back` consumes the `Response`.
+    //
+    // If `sniff_content_type` is set, a missing Content-Type is guessed
+    // from the body instead of defaulting to text/plain. An error leaves
+    // the response cut short, so the connection must then be closed.
     #[doc(hidden)]
-    pub fn write_back(mut self, http_res: &mut HttpResponse) {
-        http_res.headers = self.headers.clone();
+    pub fn write_back<W: Writer>(mut self, req: &Request, w: &mut W,
+                                 sniff_content_type: bool) -> IoResult<()> {
+        self.write(req, w, sniff_content_type)
+    }
 
-        // Default to a 404 if no response code was set
-        http_res.status = self.status.clone().unwrap_or(NotFound);
+    /// Serialize the complete response, as it would be sent to the client
+    /// in answer to `req`, to `w`.
+    ///
+    /// The status line, headers and body are written in HTTP/1.1 wire
+    /// format, exactly as the server sends them, with Content-Length and
+    /// Content-Type set as for a real response. A streaming body is written
+    /// with chunked transfer encoding. This consumes the body, so tests can
+    /// serialize a response to a `Vec<u8>` and assert on the exact bytes.
+    pub fn write_to<W: Writer>(&mut self, req: &Request, w: &mut W) -> IoResult<()> {
+        self.write(req, w, false)
+    }
 
-        // Read the body into the http_res body
-        let _ = match self.body.read_to_end() {
-            Ok(body) => {
-                let plain_txt: MediaType = get_content_type("txt").unwrap();
+    fn write<W: Writer>(&mut self, req: &Request, w: &mut W, sniff_content_type: bool) -> IoResult<()> {
+        if !self.allows_body() {
+            // The response ends with its headers, so a streamed body is
+            // dropped along with its framing.
+            self.streaming = false;
+            self.headers.transfer_encoding = None;
+        }
+        let chunked = self.streaming && self.headers.content_length.is_none();
+        if !chunked && !self.trailers.is_empty() {
+            let _ = self.headers.extensions.remove(&"Trailer".to_string());
+        }
+        let body = if self.streaming {
+            if chunked { self.headers.transfer_encoding = Some(vec![Chunked]); }
+            if self.headers.content_type.is_none() {
+                self.headers.content_type = get_content_type("txt");
+            }
+            None
+        } else {
+            match self.finish(req, sniff_content_type) {
+                Ok(body) => body,
+                Err(e) => {
+                    // Nothing has been sent yet, so the client can still be told.
+                    error!("Error reading body: {}", e);
+                    self.serve(InternalServerError, "Internal Server Error");
+                    try!(self.finish(req, false))
+                }
+            }
+        };
 
-                // Set content length and type
-                http_res.headers.content_length =
-                    Some(body.len());
-                http_res.headers.content_type =
-                    Some(http_res.headers.content_type.clone().unwrap_or(plain_txt));
+        try!(w.write(self.status_line().as_bytes()));
+        for header in self.headers.iter() {
+            try!(write!(w, "{}: {}\r\n", header.header_name(), header.header_value()));
+        }
+        for cookie in self.cookies.iter() {
+            try!(write!(w, "Set-Cookie: {}\r\n", cookie));
+        }
+        try!(w.write(b"\r\n"));
 
-                // Write the body
-                http_res.write(body.as_slice())
+        match body {
+            Some(body) => w.write(body.as_slice()),
+            None if self.streaming && req.method != Head => {
+                try!(copy_body(&mut self.body, w, chunked));
+                if !chunked { return Ok(()) }
+                try!(w.write(b"0\r\n"));
+                for &(ref name, ref value) in self.trailers.iter() {
+                    try!(write!(w, "{}: {}\r\n", name, value));
+                }
+                w.write(b"\r\n")
             },
-            Err(e) => Err(e)
-        // Catch errors from reading + writing
-        }.map_err(|e| {
-            error!("Error reading/writing body: {}", e);
-            http_res.status = InternalServerError;
-            let _ = http_res.write(b"Internal Server Error")
-                .map_err(|e| error!("Error writing error message: {}", e));
-        });
+            None => Ok(())
+        }
+    }
+
+    // "HTTP/1.1 <code> <reason>\r\n", with the custom reason if one was
+    // set for the code.
+    fn status_line(&self) -> String {
+        let status = self.status.clone().unwrap_or(NotFound);
+        let reason = match self.reason {
+            Some((ref code, ref reason)) if *code == status.code() => reason.clone(),
+            _ => status.reason().as_slice().to_string()
+        };
+        format!("HTTP/1.1 {} {}\r\n", status.code(), reason)
+    }
+
+    // Whether a response with this status can have a body. 1xx, 204 and
+    // 304 responses never do, and are sent without a Content-Length.
+    fn allows_body(&self) -> bool {
+        match self.status {
+            Some(ref status) => {
+                let code = status.code();
+                code >= 200 && code != 204 && code != 304
+            },
+            None => true
+        }
+    }
+
+    // Read the whole body, set its Content-Length and default Content-Type,
+    // and give the bytes to send.
+    //
+    // A response to HEAD reports the Content-Length the same GET would
+    // have, but sends no body. A response whose status allows no body gets
+    // neither header, and any body it was given is left unsent.
+    fn finish(&mut self, req: &Request, sniff_content_type: bool) -> IoResult<Option<Vec<u8>>> {
+        if !self.allows_body() {
+            self.headers.content_length = None;
+            return Ok(None);
+        }
+
+        let body = try!(self.body.read_to_end());
+
+        let default: MediaType = if sniff_content_type {
+            sniff(body.as_slice())
+        } else {
+            get_content_type("txt").unwrap()
+        };
+
+        // Set content length and type
+        self.headers.content_length = Some(body.len());
+        self.headers.content_type = Some(self.headers.content_type.clone().unwrap_or(default));
+
+        Ok(if req.method == Head { None } else { Some(body) })
+    }
+}
+
+// The body given to `send_reader`, which counts the bytes read from it
+// towards `bytes_sent`, with their chunk framing if the body is chunked.
+struct CountingReader<R> {
+    inner: R,
+    streamed: Arc<AtomicUint>,
+    chunked: bool
+}
+
+impl<R: Reader> Reader for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let n = try!(self.inner.read(buf));
+        let framed = if self.chunked { format!("{:x}", n).len() + 2 + n + 2 } else { n };
+        let _ = self.streamed.fetch_add(framed, SeqCst);
+        Ok(n)
+    }
+}
+
+// Stands in for a body which could not be read, giving its error again.
+struct FailedReader {
+    error: IoError
+}
+
+impl Reader for FailedReader {
+    fn read(&mut self, _: &mut [u8]) -> IoResult<uint> {
+        Err(self.error.clone())
+    }
+}
+
+// Parse a media type such as `text/html; charset=utf-8`.
+fn parse_media_type(value: &str) -> Option<MediaType> {
+    let mut parts = value.split(';').map(|part| part.trim());
+    let mut essence = parts.next().unwrap_or("").splitn('/', 1);
+    let (type_, subtype) = match (essence.next(), essence.next()) {
+        (Some(type_), Some(subtype)) if !type_.is_empty() && !subtype.is_empty() =>
+            (type_.to_ascii_lower(), subtype.to_ascii_lower()),
+        _ => return None
+    };
+
+    let mut parameters = vec![];
+    for parameter in parts.filter(|part| !part.is_empty()) {
+        let mut pair = parameter.splitn('=', 1);
+        match (pair.next(), pair.next()) {
+            (Some(key), Some(value)) => parameters.push((key.trim().to_ascii_lower(),
+                                                        value.trim().trim_chars('"').to_string())),
+            _ => return None
+        }
+    }
+
+    Some(MediaType { type_: type_, subtype: subtype, parameters: parameters })
+}
+
+// Copy `body` to `w` until it ends, flushing each piece as it is read so
+// that the client gets it at once, and framing it as a chunk if `chunked`.
+//
+// An error leaves the body unfinished: a chunked body lacks its last chunk
+// and a body of known length is short, so that the client can tell.
+fn copy_body<W: Writer>(body: &mut Box<Reader>, w: &mut W, chunked: bool) -> IoResult<()> {
+    let mut buf = [0u8, ..8192];
+    loop {
+        let n = match body.read(buf) {
+            Ok(n) => n,
+            Err(ref e) if e.kind == EndOfFile => return Ok(()),
+            Err(e) => return Err(e)
+        };
+        // An empty chunk would end the body.
+        if n == 0 { continue }
+        if chunked { try!(write!(w, "{:x}\r\n", n)); }
+        try!(w.write(buf.slice_to(n)));
+        if chunked { try!(w.write(b"\r\n")); }
+        try!(w.flush());
+    }
+}
+
+// The body fed by a `ResponseStream`, which tells it when the response
+// starts to be sent and as each chunk is taken off the queue.
+struct StreamReader {
+    receiver: Receiver<Vec<u8>>,
+    taken: Sender<()>,
+    started: Arc<AtomicBool>,
+    chunk: Vec<u8>,
+    pos: uint
+}
+
+impl Reader for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        self.started.store(true, SeqCst);
+        while self.pos == self.chunk.len() {
+            match self.receiver.recv_opt() {
+                Ok(chunk) => {
+                    let _ = self.taken.send_opt(());
+                    self.chunk = chunk;
+                    self.pos = 0;
+                },
+                Err(()) => return Err(standard_error(EndOfFile))
+            }
+        }
+
+        let n = buf.copy_from(self.chunk.slice_from(self.pos));
+        self.pos += n;
+        Ok(n)
     }
 }
 
+// `value` without any control characters but tab.
+fn strip_controls(value: &str) -> String {
+    if !value.chars().any(is_forbidden) { return value.to_string() }
+
+    warn!("Stripped control characters from header: {}", value.escape_default());
+    value.chars().filter(|c| !is_forbidden(*c)).collect()
+}
+
+fn is_forbidden(c: char) -> bool {
+    (c < ' ' && c != '\t') || c == '\x7f'
+}
+
+// Whether `c` may appear in a header name, a token in RFC 7230.
+fn is_token_char(c: char) -> bool {
+    (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || (c >= '0' && c <= '9') ||
+        "!#$%&'*+-.^_`|~".contains_char(c)
+}
+
+// The modification time of the file at `path`, truncated to whole
+// seconds as HTTP dates have no finer resolution.
+fn modified_at(path: &Path) -> IoResult<Tm> {
+    let stat = try!(path.stat());
+    Ok(at_utc(Timespec::new((stat.modified / 1000) as i64, 0)))
+}
+
+// An entity tag without its weakness indicator.
+fn weak_tag<'a>(tag: &'a str) -> &'a str {
+    if tag.starts_with("W/") { tag.slice_from(2) } else { tag }
+}
+
+// Whether a resource last modified at `mtime` has changed since `since`.
+fn is_modified(mtime: &Tm, since: &Tm) -> bool {
+    mtime.to_timespec().sec > since.to_timespec().sec
+}
+
 #[test]
 fn matches_content_type () {
     let path = &Path::new("test.txt");
@@ -108,3 +817,474 @@ fn matches_content_type () {
     assert_eq!(content_type.type_.as_slice(), "text");
     assert_eq!(content_type.subtype.as_slice(), "plain");
 }
+
+#[cfg(test)]
+mod test {
+    use std::ascii::StrAsciiExt;
+    use std::io::{IoResult, File, TempDir, MemReader, BrokenPipe, OtherIoError, standard_error};
+    use std::io::timer::sleep;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUint, SeqCst};
+    use time::{Timespec, at_utc};
+    use http::method::{Get, Head};
+    use http::status::{NotModified, NotFound};
+    use OkStatus = http::status::Ok;
+
+    use contenttype::get_content_type;
+
+    use super::{modified_at, Response, InvalidValue, STREAM_BUFFER};
+    use super::super::mock;
+
+    fn temp_file(dir: &TempDir) -> Path {
+        let path = dir.path().join("index.html");
+        File::create(&path).write(b"<h1>Hello</h1>").unwrap();
+        path
+    }
+
+    #[test]
+    fn not_modified_since() {
+        let dir = TempDir::new("iron").unwrap();
+        let path = temp_file(&dir);
+
+        let mut req = mock::request(Get, "http://localhost/index.html");
+        req.headers.if_modified_since = Some(modified_at(&path).unwrap());
+
+        let mut res = mock::response();
+        res.serve_file_if_modified(&req, &path).unwrap();
+
+        assert_eq!(res.status, Some(NotModified));
+        assert_eq!(res.body.read_to_end().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn advertises_no_ranges() {
+        let dir = TempDir::new("iron").unwrap();
+        let path = temp_file(&dir);
+
+        let mut res = mock::response();
+        res.serve_file(&path).unwrap();
+        assert!(res.header_pairs().contains(&("Accept-Ranges".to_string(), "none".to_string())));
+
+        let mut res = mock::response();
+        let _ = res.stream();
+        assert!(res.header_pairs().contains(&("Accept-Ranges".to_string(), "none".to_string())));
+    }
+
+    #[test]
+    fn modified_since() {
+        let dir = TempDir::new("iron").unwrap();
+        let path = temp_file(&dir);
+        let mtime = modified_at(&path).unwrap();
+
+        let mut req = mock::request(Get, "http://localhost/index.html");
+        req.headers.if_modified_since =
+            Some(at_utc(Timespec::new(mtime.to_timespec().sec - 3600, 0)));
+
+        let mut res = mock::response();
+        res.serve_file_if_modified(&req, &path).unwrap();
+
+        assert_eq!(res.status, Some(OkStatus));
+        assert_eq!(res.headers.last_modified, Some(mtime));
+        assert_eq!(res.body.read_to_end().unwrap(), b"<h1>Hello</h1>".to_vec());
+    }
+
+    #[test]
+    fn lists_all_headers() {
+        let mut res = Response::new();
+        res.headers.cache_control = Some("no-cache".to_string());
+        res.set_header("X-Powered-By", "Iron");
+
+        let pairs = res.header_pairs();
+        assert!(pairs.contains(&("Cache-Control".to_string(), "no-cache".to_string())));
+        assert!(pairs.contains(&("X-Powered-By".to_string(), "Iron".to_string())));
+        assert_eq!(pairs.len(), 2);
+    }
+
+    #[test]
+    fn head_reports_get_length() {
+        let mut get = Response::new();
+        get.serve(OkStatus, "Hello, world!");
+        let body = get.finish(&mock::request(Get, "http://localhost/"), false).unwrap();
+
+        let mut head = Response::new();
+        head.serve(OkStatus, "Hello, world!");
+        let no_body = head.finish(&mock::request(Head, "http://localhost/"), false).unwrap();
+
+        assert_eq!(body, Some(b"Hello, world!".to_vec()));
+        assert_eq!(no_body, None);
+        assert_eq!(head.headers.content_length, get.headers.content_length);
+        assert_eq!(head.headers.content_length, Some(13));
+    }
+
+    #[test]
+    fn sets_connection_close() {
+        let mut res = Response::new();
+        assert!(!res.is_connection_close());
+        res.set_connection_close();
+        assert!(res.is_connection_close());
+        assert!(res.header_pairs().contains(&("Connection".to_string(), "close".to_string())));
+    }
+
+    #[test]
+    fn keeps_alive_unless_closed() {
+        let mut res = Response::new();
+        assert!(res.will_keep_alive());
+        res.set_connection_close();
+        assert!(!res.will_keep_alive());
+
+        let mut res = Response::new();
+        res.close_requested = true;
+        assert!(!res.will_keep_alive());
+    }
+
+    #[test]
+    fn stream_delivers_flushed_chunks() {
+        let mut res = Response::new();
+        let mut stream = res.stream();
+        let mut buf = [0u8, ..16];
+
+        stream.write(b"first").unwrap();
+        stream.flush().unwrap();
+        let n = res.body.read(buf).unwrap();
+        assert_eq!(buf.slice_to(n), b"first");
+
+        stream.write(b"second").unwrap();
+        drop(stream);
+        assert_eq!(res.body.read_to_end().unwrap(), b"second".to_vec());
+    }
+
+    #[test]
+    fn slow_client_throttles_stream() {
+        let mut res = Response::new();
+        let mut stream = res.stream();
+        let flushed = Arc::new(AtomicUint::new(0));
+        let counter = flushed.clone();
+
+        // Start sending the response.
+        let mut buf = [0u8, ..5];
+        stream.write(b"start").unwrap();
+        stream.flush().unwrap();
+        assert_eq!(res.body.read(buf).unwrap(), 5);
+
+        spawn(proc() {
+            for _ in range(0u, 20) {
+                stream.write(b"chunk").unwrap();
+                stream.flush().unwrap();
+                let _ = counter.fetch_add(1, SeqCst);
+            }
+        });
+
+        for read in range(1u, 21) {
+            sleep(10);
+            // The writer is at most a full buffer, plus the chunk it is
+            // blocked on, ahead of the client.
+            assert!(flushed.load(SeqCst) <= read - 1 + STREAM_BUFFER + 1);
+            assert_eq!(res.body.read(buf).unwrap(), 5);
+        }
+    }
+
+    #[test]
+    fn keeps_chunks_flushed_before_sending() {
+        let mut res = Response::new();
+        let mut stream = res.stream();
+        for _ in range(0, STREAM_BUFFER * 2) {
+            stream.write(b"x").unwrap();
+            stream.flush().unwrap();
+        }
+        drop(stream);
+
+        assert_eq!(res.body.read_to_end().unwrap(), Vec::from_elem(STREAM_BUFFER * 2, b'x'));
+    }
+
+    #[test]
+    fn streams_broken_pipe_once_dropped() {
+        let mut res = Response::new();
+        let mut stream = res.stream();
+        drop(res);
+
+        stream.write(b"lost").unwrap();
+        assert_eq!(stream.flush().unwrap_err().kind, BrokenPipe);
+    }
+
+    fn wire(res: &mut Response) -> (IoResult<()>, String) {
+        let mut wire = vec![];
+        let result = res.write_to(&mock::request(Get, "http://localhost/"), &mut wire);
+        (result, String::from_utf8(wire).unwrap())
+    }
+
+    #[test]
+    fn sends_reader_with_length() {
+        let mut res = Response::new();
+        res.send_reader(MemReader::new(b"Hello, reader!".to_vec()), Some(14));
+
+        let (result, wire) = wire(&mut res);
+        assert!(result.is_ok());
+        assert!(wire.as_slice().contains("\r\nContent-Length: 14\r\n"));
+        assert!(!wire.as_slice().contains("Transfer-Encoding"));
+        assert!(wire.as_slice().ends_with("\r\n\r\nHello, reader!"));
+    }
+
+    #[test]
+    fn sends_reader_chunked() {
+        let mut res = Response::new();
+        res.send_reader(MemReader::new(b"Hello, reader!".to_vec()), None);
+
+        let (result, wire) = wire(&mut res);
+        assert!(result.is_ok());
+        assert!(wire.as_slice().contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(wire.as_slice().ends_with("\r\n\r\ne\r\nHello, reader!\r\n0\r\n\r\n"));
+    }
+
+    // Gives a first chunk, then fails.
+    struct FailingReader { done: bool }
+
+    impl Reader for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+            if self.done { return Err(standard_error(OtherIoError)) }
+            self.done = true;
+            buf[0] = b'x';
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn aborts_reader_body_on_error() {
+        let mut res = Response::new();
+        res.send_reader(FailingReader { done: false }, None);
+
+        let (result, wire) = wire(&mut res);
+        assert_eq!(result.unwrap_err().kind, OtherIoError);
+        assert!(wire.as_slice().ends_with("1\r\nx\r\n"));
+    }
+
+    #[test]
+    fn counts_bytes_sent() {
+        let mut res = Response::new();
+        res.serve(OkStatus, "Hello");
+        res.set_header("X-A", "b");
+        res.set_cookie("theme=dark");
+
+        // HTTP/1.1 200 OK\r\n, X-A: b\r\n, Content-Length: 5\r\n,
+        // Set-Cookie: theme=dark\r\n, Content-Type, \r\n, Hello
+        let content_type = format!("Content-Type: {}\r\n", get_content_type("txt").unwrap()).len();
+        let sent = res.bytes_sent().unwrap();
+        assert_eq!(sent, 17 + 8 + 19 + 24 + content_type + 2 + 5);
+
+        let (result, wire) = wire(&mut res);
+        assert!(result.is_ok());
+        assert_eq!(sent, wire.len());
+    }
+
+    #[test]
+    fn counts_streamed_bytes() {
+        let mut res = Response::new();
+        let mut stream = res.stream();
+
+        // HTTP/1.1 200 OK\r\n, Transfer-Encoding: chunked\r\n,
+        // Accept-Ranges: none\r\n, Content-Type, \r\n
+        let content_type = format!("Content-Type: {}\r\n", get_content_type("txt").unwrap()).len();
+        assert_eq!(res.bytes_sent().unwrap(), 17 + 28 + 21 + content_type + 2);
+
+        stream.write(b"abc").unwrap();
+        stream.flush().unwrap();
+        assert_eq!(res.bytes_sent().unwrap(), 17 + 28 + 21 + content_type + 2 + 8);
+    }
+
+    #[test]
+    fn keeps_body_error_from_bytes_sent() {
+        let mut res = Response::new();
+        res.status = Some(OkStatus);
+        res.body = box FailingReader { done: false } as Box<Reader>;
+        assert_eq!(res.bytes_sent().unwrap_err().kind, OtherIoError);
+
+        let (result, wire) = wire(&mut res);
+        assert!(result.is_ok());
+        assert!(wire.as_slice().starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+    }
+
+    #[test]
+    fn sends_each_cookie_on_its_own_line() {
+        let mut res = Response::new();
+        res.set_cookie("theme=dark; Path=/");
+        res.set_cookie("lang=is");
+        res.set_cookie("session=abc; HttpOnly");
+        res.serve(OkStatus, "");
+        assert!(res.has_header("set-cookie"));
+
+        let (result, wire) = wire(&mut res);
+        assert!(result.is_ok());
+        let cookies: Vec<&str> = wire.as_slice().split_str("\r\n")
+            .filter(|line| line.starts_with("Set-Cookie: ")).collect();
+        assert_eq!(cookies, vec!["Set-Cookie: theme=dark; Path=/", "Set-Cookie: lang=is",
+                                 "Set-Cookie: session=abc; HttpOnly"]);
+    }
+
+    #[test]
+    fn replaces_headers_case_insensitively() {
+        let mut res = Response::new();
+        res.set_header("x-foo", "a");
+        res.set_header("X-Foo", "b");
+
+        let foos: Vec<(String, String)> = res.header_pairs().move_iter()
+            .filter(|&(ref name, _)| name.as_slice().eq_ignore_ascii_case("X-Foo")).collect();
+        assert_eq!(foos, vec![("X-Foo".to_string(), "b".to_string())]);
+    }
+
+    #[test]
+    fn sets_typed_headers_through_their_field() {
+        let mut res = Response::new();
+        res.set_header("Cache-Control", "no-cache");
+        res.set_header("cache-control", "no-store");
+        res.set_header("Content-Type", "text/html; charset=utf-8");
+
+        assert_eq!(res.headers.cache_control, Some("no-store".to_string()));
+        assert!(res.headers.extensions.is_empty());
+        let content_type = res.headers.content_type.unwrap();
+        assert_eq!(content_type.type_.as_slice(), "text");
+        assert_eq!(content_type.subtype.as_slice(), "html");
+        assert_eq!(content_type.parameters, vec![("charset".to_string(), "utf-8".to_string())]);
+    }
+
+    #[test]
+    fn strips_header_injection() {
+        let mut res = Response::new();
+        res.set_header("X-Name", "a\r\nSet-Cookie: evil=1");
+        assert_eq!(res.headers.extensions.find_equiv(&"X-Name"), Some(&"aSet-Cookie: evil=1".to_string()));
+
+        let mut wire = vec![];
+        res.write_to(&mock::request(Get, "http://localhost/"), &mut wire).unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(!wire.as_slice().split_str("\r\n").any(|line| line.starts_with("Set-Cookie")));
+    }
+
+    #[test]
+    fn sends_custom_reason() {
+        let mut res = Response::new();
+        res.set_status_with_reason(200, "Fine").unwrap();
+
+        assert_eq!(res.status, Some(OkStatus));
+
+        let mut wire = vec![];
+        res.write_to(&mock::request(Get, "http://localhost/"), &mut wire).unwrap();
+        assert!(String::from_utf8(wire).unwrap().as_slice().starts_with("HTTP/1.1 200 Fine\r\n"));
+    }
+
+    #[test]
+    fn drops_custom_reason_with_its_status() {
+        let mut res = Response::new();
+        res.set_status_with_reason(200, "Fine").unwrap();
+        res.serve(NotFound, "");
+
+        let (_, wire) = wire(&mut res);
+        assert!(wire.as_slice().starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn sends_unregistered_status_with_reason() {
+        let mut res = Response::new();
+        res.set_status_with_reason(299, "Custom").unwrap();
+        assert_eq!(res.status.as_ref().map(|status| status.code()), Some(299));
+
+        let (_, wire) = wire(&mut res);
+        assert!(wire.as_slice().starts_with("HTTP/1.1 299 Custom\r\n"));
+    }
+
+    #[test]
+    fn rejects_reason_with_newline() {
+        let mut res = Response::new();
+        assert_eq!(res.set_status_with_reason(200, "OK\r\nSet-Cookie: a=b"), Err(InvalidValue));
+        assert_eq!(res.status, None);
+    }
+
+    #[test]
+    fn writes_wire_format() {
+        let mut res = Response::new();
+        res.serve(OkStatus, "Hello, world!");
+
+        let mut wire = vec![];
+        res.write_to(&mock::request(Get, "http://localhost/"), &mut wire).unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        let (head, body) = match wire.as_slice().split_str("\r\n\r\n").collect::<Vec<&str>>().as_slice() {
+            [head, body] => (head.to_string(), body.to_string()),
+            _ => fail!("No end of headers in {}", wire)
+        };
+        let lines: Vec<&str> = head.as_slice().split_str("\r\n").collect();
+
+        assert_eq!(lines[0], "HTTP/1.1 200 OK");
+        assert!(lines.contains(&"Content-Length: 13"));
+        assert!(lines.iter().any(|line| line.starts_with("Content-Type: text/plain")));
+        assert_eq!(body.as_slice(), "Hello, world!");
+    }
+
+    #[test]
+    fn writes_streamed_body_chunked() {
+        let mut res = Response::new();
+        {
+            let mut stream = res.stream();
+            stream.write(b"Hello").unwrap();
+        }
+
+        let (result, wire) = wire(&mut res);
+        assert!(result.is_ok());
+        assert!(wire.as_slice().starts_with("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(wire.as_slice().contains("\r\nAccept-Ranges: none\r\n"));
+        assert!(wire.as_slice().contains("\r\nContent-Type: text/plain"));
+        assert!(wire.as_slice().ends_with("\r\n\r\n5\r\nHello\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn writes_trailers_after_last_chunk() {
+        let mut res = Response::new();
+        {
+            let mut stream = res.stream();
+            stream.write(b"Hello").unwrap();
+        }
+        res.set_trailer("Content-MD5", "8b1a9953c4611296a827abf8c47804d7").unwrap();
+
+        let mut wire = vec![];
+        res.write_to(&mock::request(Get, "http://localhost/"), &mut wire).unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(wire.as_slice().contains("\r\nTrailer: Content-MD5\r\n"));
+        assert!(wire.as_slice().ends_with(
+            "5\r\nHello\r\n0\r\nContent-MD5: 8b1a9953c4611296a827abf8c47804d7\r\n\r\n"));
+    }
+
+    #[test]
+    fn strips_trailer_injection() {
+        let mut res = Response::new();
+        {
+            let mut stream = res.stream();
+            stream.write(b"Hello").unwrap();
+        }
+        res.set_trailer("X-Sum", "x\r\nEvil: 1").unwrap();
+        assert_eq!(res.set_trailer("X-Bad\r\nEvil", "1"), Err(InvalidValue));
+        assert_eq!(res.set_trailer("X Bad", "1"), Err(InvalidValue));
+        assert_eq!(res.set_trailer("", "1"), Err(InvalidValue));
+
+        let (result, wire) = wire(&mut res);
+        assert!(result.is_ok());
+        assert!(wire.as_slice().contains("\r\nTrailer: X-Sum\r\n"));
+        assert!(wire.as_slice().ends_with("0\r\nX-Sum: xEvil: 1\r\n\r\n"));
+    }
+
+    #[test]
+    fn drops_trailers_of_unchunked_responses() {
+        let mut res = Response::new();
+        res.set_trailer("Content-MD5", "8b1a9953c4611296a827abf8c47804d7").unwrap();
+        res.serve(OkStatus, "Hello");
+
+        let (result, wire) = wire(&mut res);
+        assert!(result.is_ok());
+        assert!(!wire.as_slice().contains("Trailer"));
+        assert!(!wire.as_slice().contains("Content-MD5"));
+        assert!(wire.as_slice().ends_with("\r\n\r\nHello"));
+    }
+
+    #[test]
+    #[should_fail]
+    fn stream_cannot_be_reset() {
+        let mut res = Response::new();
+        let _stream = res.stream();
+        res.serve(OkStatus, "reset");
+    }
+}
@@ -0,0 +1,390 @@
+//! Exposes the `Router` middleware, which dispatches requests to
+//! handlers by method and path.
+
+use std::collections::HashMap;
+
+use http::method::Method;
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue};
+use super::chain::Chain;
+use super::chain::stackchain::StackChain;
+
+/// The parameters captured from the path by the matching route.
+///
+/// The `Router` stores `Params` in `Request::alloy` before calling the
+/// route's handler.
+#[deriving(Clone, Show)]
+pub struct Params {
+    map: HashMap<String, String>
+}
+
+impl Params {
+    /// Get the value captured by the segment called `name`.
+    pub fn find<'a>(&'a self, name: &str) -> Option<&'a str> {
+        self.map.find_equiv(&name).map(|value| value.as_slice())
+    }
+}
+
+/// The pattern of the route which matched the request, such as
+/// `/users/:id`.
+///
+/// The `Router` stores it in `Request::alloy` along with the `Params`, for
+/// logging and metrics which should group requests by route rather than
+/// by path.
+#[deriving(Clone, PartialEq, Show)]
+pub struct MatchedRoute(pub String);
+
+#[deriving(Clone, Show)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String)
+}
+
+/// A path pattern, such as `/users/:id` or `/files/*path`.
+///
+/// A segment starting with `:` matches any single segment, and one
+/// starting with `*` matches the rest of the path, slashes included.
+/// `*` segments are only allowed at the end of a pattern.
+///
+/// The rest of the path is captured joined by `/`, so it can't match if
+/// one of its segments contains an encoded slash, or is `.` or `..`:
+/// that would let `/files/..%2F..%2Fetc%2Fpasswd` capture a path leaving
+/// the tree the route serves.
+#[deriving(Clone, Show)]
+pub struct Pattern {
+    source: String,
+    segments: Vec<Segment>
+}
+
+impl Pattern {
+    /// Parse a pattern, failing if a `*` segment is not the last one.
+    pub fn new(source: &str) -> Pattern {
+        let pattern = source.trim_left_chars('/');
+        let parts: Vec<&str> = pattern.split('/').collect();
+        let last = parts.len() - 1;
+
+        Pattern {
+            source: source.to_string(),
+            segments: parts.iter().enumerate().map(|(i, part)| {
+                if part.starts_with(":") {
+                    Param(part.slice_from(1).to_string())
+                } else if part.starts_with("*") {
+                    if i != last {
+                        fail!("Wildcard segment {} must be the last one in {}.", part, pattern)
+                    }
+                    Wildcard(part.slice_from(1).to_string())
+                } else {
+                    Literal(part.to_string())
+                }
+            }).collect()
+        }
+    }
+
+    /// The pattern as it was given to `new`.
+    pub fn as_str<'a>(&'a self) -> &'a str {
+        self.source.as_slice()
+    }
+
+    /// Match the decoded segments of a path against this pattern,
+    /// producing the captured `Params` if it matches.
+    pub fn matches(&self, path: &[String]) -> Option<Params> {
+        let mut map = HashMap::new();
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            match *segment {
+                Literal(ref literal) => {
+                    if i >= path.len() || path[i] != *literal { return None }
+                },
+                Param(ref name) => {
+                    if i >= path.len() || path[i].is_empty() { return None }
+                    let _ = map.insert(name.clone(), path[i].clone());
+                },
+                Wildcard(ref name) => {
+                    if i >= path.len() { return None }
+                    let escapes = path.slice_from(i).iter().any(|segment| {
+                        segment.as_slice().contains_char('/') ||
+                            segment.as_slice() == "." || segment.as_slice() == ".."
+                    });
+                    if escapes { return None }
+                    let _ = map.insert(name.clone(), path.slice_from(i).connect("/"));
+                    return Some(Params { map: map });
+                }
+            }
+        }
+
+        if path.len() == self.segments.len() { Some(Params { map: map }) } else { None }
+    }
+}
+
+// A route's middleware and handler are held in a `StackChain` of their
+// own, which is dispatched as a whole when the route matches.
+#[deriving(Clone)]
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    chain: StackChain
+}
+
+/// `Middleware` which calls the handler of the first route matching the
+/// request's method and decoded path.
+///
+/// ```ignore
+/// let mut router = Router::new();
+/// router.route(Get, "/users/:id", FromFn::new(show_user));
+/// router.route(Get, "/files/*path", FromFn::new(serve_file));
+/// server.chain.link(router);
+/// ```
+///
+/// Routes match methods exactly, so `PATCH` and extension methods, which
+/// the parser keeps as `ExtensionMethod` with their token as sent, are
+/// routed like any other:
+///
+/// ```ignore
+/// router.route(Patch, "/users/:id", FromFn::new(update_user));
+/// router.route(ExtensionMethod("REPORT".to_string()), "/calendar", FromFn::new(report));
+/// ```
+///
+/// The `Params` captured by the route, and its pattern as a `MatchedRoute`,
+/// are stored in `Request::alloy`.
+/// The status returned by the handler is returned to the `Chain`, and
+/// requests matching no route `Continue`.
+///
+/// A route may have `Middleware` of its own, which only run for requests
+/// matching that route. They are entered after, and exited before, the
+/// `Middleware` of the enclosing `Chain`:
+///
+/// ```ignore
+/// router.route_with(Get, "/admin/*rest", vec![box Auth as Box<Middleware + Send>],
+///                   FromFn::new(admin));
+/// ```
+#[deriving(Clone)]
+pub struct Router {
+    routes: Vec<Route>
+}
+
+impl Router {
+    /// Create a new `Router` with no routes.
+    pub fn new() -> Router {
+        Router { routes: vec![] }
+    }
+
+    /// Add a route calling `handler` for `method` requests matching `pattern`.
+    ///
+    /// Fails if `pattern` is invalid, see `Pattern`.
+    pub fn route<M: Middleware>(&mut self, method: Method, pattern: &str, handler: M) {
+        self.route_with(method, pattern, vec![], handler);
+    }
+
+    /// Add a route which runs `middleware`, in order, before `handler`
+    /// for `method` requests matching `pattern`.
+    ///
+    /// Fails if `pattern` is invalid, see `Pattern`.
+    pub fn route_with<M: Middleware>(&mut self, method: Method, pattern: &str,
+                                     middleware: Vec<Box<Middleware + Send>>, handler: M) {
+        let mut chain: StackChain = middleware.move_iter().collect();
+        chain.link(handler);
+
+        self.routes.push(Route {
+            method: method,
+            pattern: Pattern::new(pattern),
+            chain: chain
+        });
+    }
+}
+
+impl Middleware for Router {
+    fn enter(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        for route in self.routes.mut_iter() {
+            if route.method != req.method { continue }
+
+            match route.pattern.matches(req.path.as_slice()) {
+                Some(params) => {
+                    req.alloy.insert::<Params>(params);
+                    req.alloy.insert(MatchedRoute(route.pattern.as_str().to_string()));
+                    // The route's exits run here, as the enclosing chain
+                    // does not exit Middleware which Unwind.
+                    return route.chain.dispatch(req, res);
+                },
+                None => ()
+            }
+        }
+
+        Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use http::method::{Get, Post, Patch, ExtensionMethod};
+
+    use super::{Router, Pattern, Params, MatchedRoute};
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::middleware::{Middleware, Status, Continue, Unwind, FromFn};
+
+    fn path(path: &str) -> Vec<String> {
+        mock::request(Get, format!("http://localhost{}", path).as_slice()).path
+    }
+
+    #[test]
+    fn matches_literals_and_params() {
+        let params = Pattern::new("/users/:id").matches(path("/users/42").as_slice()).unwrap();
+        assert_eq!(params.find("id"), Some("42"));
+        assert!(Pattern::new("/users/:id").matches(path("/users").as_slice()).is_none());
+        assert!(Pattern::new("/users/:id").matches(path("/users/42/posts").as_slice()).is_none());
+    }
+
+    #[test]
+    fn wildcard_captures_rest_of_path() {
+        let params = Pattern::new("/files/*path").matches(path("/files/a/b/c").as_slice()).unwrap();
+        assert_eq!(params.find("path"), Some("a/b/c"));
+    }
+
+    #[test]
+    fn wildcard_refuses_path_traversal() {
+        let pattern = Pattern::new("/files/*path");
+        let dots = vec!["files".to_string(), "a".to_string(), "..".to_string()];
+        assert!(pattern.matches(dots.as_slice()).is_none());
+        assert!(pattern.matches(path("/files/..%2F..%2Fetc%2Fpasswd").as_slice()).is_none());
+        assert!(pattern.matches(path("/files/a%2Fb").as_slice()).is_none());
+    }
+
+    #[test]
+    #[should_fail]
+    fn rejects_non_final_wildcard() {
+        let mut router = Router::new();
+        router.route(Get, "/files/*path/edit", FromFn::new(handled));
+    }
+
+    fn handled(req: &mut Request, res: &mut Response) -> Status {
+        let id = req.alloy.find::<Params>().unwrap().find("id").unwrap().to_string();
+        res.serve(::http::status::Ok, id);
+        Unwind
+    }
+
+    #[test]
+    fn dispatches_by_method_and_path() {
+        let mut router = Router::new();
+        router.route(Post, "/users/:id", FromFn::new(handled));
+        router.route(Get, "/users/:id", FromFn::new(handled));
+
+        let mut res = mock::response();
+        match router.enter(&mut mock::request(Get, "http://localhost/users/42"), &mut res) {
+            Unwind => (),
+            _ => fail!("Router did not call the matched handler.")
+        }
+        assert_eq!(res.body.read_to_end().unwrap(), b"42".to_vec());
+
+        match router.enter(&mut mock::request(Get, "http://localhost/posts/42"),
+                           &mut mock::response()) {
+            Continue => (),
+            _ => fail!("Router did not continue on an unmatched path.")
+        }
+    }
+
+    fn routed(router: &mut Router, req: &mut Request) -> Option<Vec<u8>> {
+        let mut res = mock::response();
+        match router.enter(req, &mut res) {
+            Unwind => Some(res.body.read_to_end().unwrap()),
+            _ => None
+        }
+    }
+
+    #[test]
+    fn dispatches_patch() {
+        let mut router = Router::new();
+        router.route(Get, "/users/:id", FromFn::new(handled));
+        router.route(Patch, "/users/:id", FromFn::new(handled));
+
+        let mut req = mock::request(Patch, "http://localhost/users/42");
+        assert_eq!(routed(&mut router, &mut req), Some(b"42".to_vec()));
+        assert_eq!(routed(&mut router, &mut mock::request(Post, "http://localhost/users/42")), None);
+    }
+
+    #[test]
+    fn dispatches_extension_methods() {
+        let report = ExtensionMethod("REPORT".to_string());
+        let mut router = Router::new();
+        router.route(report.clone(), "/users/:id", FromFn::new(handled));
+
+        let mut req = mock::request(report, "http://localhost/users/42");
+        assert_eq!(routed(&mut router, &mut req), Some(b"42".to_vec()));
+
+        let mut req = mock::request(ExtensionMethod("MKCOL".to_string()), "http://localhost/users/42");
+        assert_eq!(routed(&mut router, &mut req), None);
+    }
+
+    #[test]
+    fn stores_matched_pattern() {
+        let mut router = Router::new();
+        router.route(Get, "/posts/:id", FromFn::new(handled));
+        router.route(Get, "/users/:id", FromFn::new(handled));
+
+        let mut req = mock::request(Get, "http://localhost/users/42");
+        let _ = router.enter(&mut req, &mut mock::response());
+        assert_eq!(req.alloy.find::<MatchedRoute>(), Some(&MatchedRoute("/users/:id".to_string())));
+    }
+
+    #[deriving(Clone)]
+    struct Trace {
+        name: &'static str,
+        handles: bool,
+        log: Arc<Mutex<Vec<String>>>
+    }
+
+    impl Middleware for Trace {
+        fn enter(&mut self, _: &mut Request, _: &mut Response) -> Status {
+            self.log.lock().push(format!("{} enter", self.name));
+            if self.handles { Unwind } else { Continue }
+        }
+
+        fn exit(&mut self, _: &mut Request, _: &mut Response) -> Status {
+            self.log.lock().push(format!("{} exit", self.name));
+            Continue
+        }
+    }
+
+    fn traced_chain(log: &Arc<Mutex<Vec<String>>>) -> StackChain {
+        let trace = |name, handles| Trace { name: name, handles: handles, log: log.clone() };
+
+        let mut router = Router::new();
+        router.route_with(Get, "/admin", vec![box trace("route", false) as Box<Middleware + Send>],
+                          trace("admin", true));
+        router.route(Get, "/public", trace("public", true));
+
+        let mut chain: StackChain = Chain::new();
+        chain.link(trace("global", false));
+        chain.link(router);
+        chain
+    }
+
+    #[test]
+    fn nests_route_middleware() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let _ = traced_chain(&log).dispatch(&mut mock::request(Get, "http://localhost/admin"),
+                                            &mut mock::response());
+
+        let expected: Vec<String> = ["global enter", "route enter", "admin enter",
+                                     "route exit", "global exit"]
+            .iter().map(|s| s.to_string()).collect();
+        assert_eq!(*log.lock(), expected);
+    }
+
+    #[test]
+    fn route_middleware_only_run_on_their_route() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let _ = traced_chain(&log).dispatch(&mut mock::request(Get, "http://localhost/public"),
+                                            &mut mock::response());
+
+        let expected: Vec<String> = ["global enter", "public enter", "global exit"]
+            .iter().map(|s| s.to_string()).collect();
+        assert_eq!(*log.lock(), expected);
+    }
+}
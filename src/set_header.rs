@@ -0,0 +1,109 @@
+//! Exposes the `SetHeader` middleware, which adds a fixed header
+//! to every response.
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue};
+
+/// `Middleware` which sets a header on every response in `exit`.
+///
+/// By default the header is only set if no other `Middleware` set it
+/// already; use `force` to always overwrite it:
+///
+/// ```ignore
+/// server.chain.link(SetHeader::new("X-Frame-Options", "DENY"));
+/// ```
+#[deriving(Clone)]
+pub struct SetHeader {
+    name: String,
+    value: String,
+    force: bool
+}
+
+impl SetHeader {
+    /// Create a new `SetHeader` setting `name` to `value`.
+    pub fn new(name: &str, value: &str) -> SetHeader {
+        SetHeader { name: name.to_string(), value: value.to_string(), force: false }
+    }
+
+    /// Set the header even if it is already present on the response.
+    pub fn force(mut self) -> SetHeader {
+        self.force = true;
+        self
+    }
+}
+
+impl Middleware for SetHeader {
+    fn exit(&mut self, _: &mut Request, res: &mut Response) -> Status {
+        if self.force || !res.has_header(self.name.as_slice()) {
+            res.set_header(self.name.as_slice(), self.value.as_slice());
+        }
+        Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ascii::StrAsciiExt;
+    use http::method::Get;
+
+    use super::SetHeader;
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::middleware::{Status, Continue, FromFn};
+
+    fn preset(_: &mut Request, res: &mut Response) -> Status {
+        res.set_header("X-Frame-Options", "SAMEORIGIN");
+        Continue
+    }
+
+    fn header(set_header: SetHeader, preset_first: bool) -> Option<String> {
+        let mut chain: StackChain = Chain::new();
+        chain.link(set_header);
+        if preset_first { chain.link(FromFn::new(preset)); }
+
+        let mut res = mock::response();
+        let _ = chain.dispatch(&mut mock::request(Get, "http://localhost/"), &mut res);
+        res.headers.extensions.find_equiv(&"X-Frame-Options").map(|v| v.clone())
+    }
+
+    #[test]
+    fn sets_header() {
+        assert_eq!(header(SetHeader::new("X-Frame-Options", "DENY"), false),
+                   Some("DENY".to_string()));
+    }
+
+    #[test]
+    fn keeps_existing_header() {
+        assert_eq!(header(SetHeader::new("X-Frame-Options", "DENY"), true),
+                   Some("SAMEORIGIN".to_string()));
+    }
+
+    fn preset_cache_control(_: &mut Request, res: &mut Response) -> Status {
+        res.headers.cache_control = Some("no-cache".to_string());
+        Continue
+    }
+
+    #[test]
+    fn forces_typed_header_once() {
+        let mut chain: StackChain = Chain::new();
+        chain.link(SetHeader::new("cache-control", "no-store").force());
+        chain.link(FromFn::new(preset_cache_control));
+
+        let mut res = mock::response();
+        let _ = chain.dispatch(&mut mock::request(Get, "http://localhost/"), &mut res);
+        let values: Vec<String> = res.header_pairs().move_iter()
+            .filter(|&(ref name, _)| name.as_slice().eq_ignore_ascii_case("Cache-Control"))
+            .map(|(_, value)| value).collect();
+        assert_eq!(values, vec!["no-store".to_string()]);
+    }
+
+    #[test]
+    fn forces_header() {
+        assert_eq!(header(SetHeader::new("X-Frame-Options", "DENY").force(), true),
+                   Some("DENY".to_string()));
+    }
+}
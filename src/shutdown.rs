@@ -0,0 +1,77 @@
+//! Exposes the `Shutdown` token, which tells long-running `Middleware`
+//! that the server is winding down.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, SeqCst};
+
+/// A flag shared between the server and every request it handles.
+///
+/// Each `Request` carries a clone of its server's token in
+/// `Request::alloy`. `Middleware` which keep a connection open, such
+/// as event streams, should poll it and end their loops once it is
+/// signaled:
+///
+/// ```ignore
+/// let shutdown = req.alloy.find::<Shutdown>().unwrap().clone();
+/// spawn(proc() {
+///     while !shutdown.is_signaled() {
+///         // Send the next event.
+///     }
+/// });
+/// ```
+///
+/// The token is obtained with `Iron::shutdown` and signaled by the
+/// application, for instance from its own signal handling, before it
+/// stops the process.
+#[deriving(Clone)]
+pub struct Shutdown {
+    flag: Arc<AtomicBool>
+}
+
+impl Shutdown {
+    /// Create a new, unsignaled token.
+    pub fn new() -> Shutdown {
+        Shutdown { flag: Arc::new(AtomicBool::new(false)) }
+    }
+
+    /// Signal this token, and all of its clones.
+    pub fn signal(&self) {
+        self.flag.store(true, SeqCst);
+    }
+
+    /// Whether the token has been signaled.
+    pub fn is_signaled(&self) -> bool {
+        self.flag.load(SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::timer::sleep;
+    use http::method::Get;
+
+    use super::Shutdown;
+    use super::super::mock;
+
+    #[test]
+    fn loop_ends_on_shutdown() {
+        let server = Shutdown::new();
+        let mut req = mock::request(Get, "http://localhost/events");
+        req.alloy.insert::<Shutdown>(server.clone());
+
+        let shutdown = req.alloy.find::<Shutdown>().unwrap().clone();
+        let (tx, rx) = channel();
+        spawn(proc() {
+            let mut events = 0u;
+            while !shutdown.is_signaled() {
+                events += 1;
+                sleep(1);
+            }
+            tx.send(events);
+        });
+
+        sleep(10);
+        server.signal();
+        assert!(rx.recv() > 0);
+    }
+}
@@ -0,0 +1,87 @@
+//! Guessing the content type of a response body from its first bytes.
+
+use http::headers::content_type::MediaType;
+
+use contenttype::get_content_type;
+
+// How many bytes of the body are looked at.
+static SNIFF_LEN: uint = 512;
+
+// Markers which, at the start of a body, identify it as HTML. They are
+// compared case-insensitively after leading whitespace, and must be
+// followed by a space or `>` so that `<p` doesn't match `<pre`.
+static HTML_MARKERS: &'static [&'static str] = &[
+    "<!doctype html", "<html", "<head", "<body", "<script", "<iframe",
+    "<h1", "<div", "<table", "<a", "<p", "<!--"
+];
+
+/// Guess the content type of `body`.
+///
+/// Bodies which start like an HTML document are `text/html`, bodies
+/// which look like text are `text/plain` and anything else is
+/// `application/octet-stream`.
+pub fn sniff(body: &[u8]) -> MediaType {
+    let head = body.slice_to(::std::cmp::min(body.len(), SNIFF_LEN));
+
+    if is_binary(head) {
+        return get_content_type("bin").unwrap();
+    }
+
+    let start: String = head.iter()
+        .skip_while(|b| (**b as char).is_whitespace())
+        .take(16)
+        .map(|b| (*b as char).to_lowercase())
+        .collect();
+
+    let start = start.as_slice();
+    let is_html = HTML_MARKERS.iter().any(|marker| {
+        start.starts_with(*marker) &&
+            (*marker == "<!--" || start.slice_from(marker.len()).starts_with(" ") ||
+             start.slice_from(marker.len()).starts_with(">"))
+    });
+    if is_html {
+        get_content_type("html").unwrap()
+    } else {
+        get_content_type("txt").unwrap()
+    }
+}
+
+// Text never contains control bytes other than whitespace.
+fn is_binary(head: &[u8]) -> bool {
+    head.iter().any(|&b| b < 0x20 && b != b'\t' && b != b'\n' && b != b'\r' && b != 0x0C)
+}
+
+#[cfg(test)]
+mod test {
+    use super::sniff;
+
+    fn sniffed(body: &[u8]) -> String {
+        let media_type = sniff(body);
+        format!("{}/{}", media_type.type_, media_type.subtype)
+    }
+
+    #[test]
+    fn sniffs_html() {
+        assert_eq!(sniffed(b"  <!DOCTYPE html><html></html>").as_slice(), "text/html");
+        assert_eq!(sniffed(b"<p>Hello</p>").as_slice(), "text/html");
+    }
+
+    #[test]
+    fn requires_tag_terminator() {
+        assert_eq!(sniffed(b"<a href=\"/\">Home</a>").as_slice(), "text/html");
+        assert_eq!(sniffed(b"<!-- comment -->").as_slice(), "text/html");
+        assert_eq!(sniffed(b"<pre>x</pre>").as_slice(), "text/plain");
+        assert_eq!(sniffed(b"<param name=x>").as_slice(), "text/plain");
+        assert_eq!(sniffed(b"<abbr>x</abbr>").as_slice(), "text/plain");
+    }
+
+    #[test]
+    fn sniffs_text() {
+        assert_eq!(sniffed(b"Hello, world!\n").as_slice(), "text/plain");
+    }
+
+    #[test]
+    fn sniffs_binary() {
+        assert_eq!(sniffed(b"\x89PNG\r\n\x1a\n\x00\x00").as_slice(), "application/octet-stream");
+    }
+}
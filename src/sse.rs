@@ -0,0 +1,145 @@
+//! Helpers for serving server-sent events.
+
+use std::io::{IoResult, IoError, InvalidInput};
+
+use http::headers::content_type::MediaType;
+
+use super::response::{Response, ResponseStream};
+
+/// A stream of server-sent events, written to a streaming `Response`.
+///
+/// Once the `Chain` has returned, each event is sent to the client as
+/// soon as it is written; events written before then wait until it has.
+/// The connection stays open until the `EventStream` is dropped, so a
+/// handler would usually move it into a separate task:
+///
+/// ```ignore
+/// fn events(_: &mut Request, res: &mut Response) -> Status {
+///     let mut events = EventStream::new(res);
+///     spawn(proc() {
+///         for (i, tick) in ticks.iter().enumerate() {
+///             if events.send_event(Some(i.to_string().as_slice()), None, tick).is_err() { break }
+///         }
+///     });
+///     Unwind
+/// }
+/// ```
+pub struct EventStream {
+    stream: ResponseStream
+}
+
+impl EventStream {
+    /// Start an event stream on `res`.
+    ///
+    /// This sets the `text/event-stream` content type and disables
+    /// caching of the response.
+    pub fn new(res: &mut Response) -> EventStream {
+        res.headers.content_type = Some(MediaType {
+            type_: "text".to_string(),
+            subtype: "event-stream".to_string(),
+            parameters: vec![]
+        });
+        res.headers.cache_control = Some("no-cache".to_string());
+        EventStream { stream: res.stream() }
+    }
+
+    /// Send a single event with an optional `id` and `event` type.
+    ///
+    /// Each line of `data`, ended by CR LF, CR or LF, is sent as its own
+    /// `data:` field. Since a line end would start another field, an `id`
+    /// or `event` containing CR or LF is refused with an `InvalidInput`
+    /// error, and nothing is sent.
+    pub fn send_event(&mut self, id: Option<&str>, event: Option<&str>, data: &str) -> IoResult<()> {
+        let forged = |field: Option<&str>| field.map_or(false, |field| has_line_end(field));
+        if forged(id) || forged(event) {
+            return Err(IoError {
+                kind: InvalidInput,
+                desc: "event id or type contains a line end",
+                detail: None
+            });
+        }
+
+        match id {
+            Some(id) => try!(write!(self.stream, "id: {}\n", id)),
+            None => ()
+        }
+        match event {
+            Some(event) => try!(write!(self.stream, "event: {}\n", event)),
+            None => ()
+        }
+        let data = data.replace("\r\n", "\n").replace("\r", "\n");
+        for line in data.as_slice().split('\n') {
+            try!(write!(self.stream, "data: {}\n", line));
+        }
+        try!(self.stream.write(b"\n"));
+        self.stream.flush()
+    }
+
+    /// Send a `:keep-alive` comment, which clients ignore but which keeps
+    /// intermediaries from closing an idle connection.
+    pub fn keep_alive(&mut self) -> IoResult<()> {
+        try!(self.stream.write(b":keep-alive\n\n"));
+        self.stream.flush()
+    }
+}
+
+fn has_line_end(field: &str) -> bool {
+    field.contains_char('\r') || field.contains_char('\n')
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::from_utf8;
+    use std::io::InvalidInput;
+
+    use super::EventStream;
+    use super::super::response::Response;
+
+    #[test]
+    fn frames_events() {
+        let mut res = Response::new();
+        {
+            let mut events = EventStream::new(&mut res);
+            events.send_event(Some("1"), Some("greeting"), "hello").unwrap();
+            events.keep_alive().unwrap();
+            events.send_event(None, None, "two\nlines").unwrap();
+        }
+
+        let body = res.body.read_to_end().unwrap();
+        assert_eq!(from_utf8(body.as_slice()).unwrap(),
+                   "id: 1\nevent: greeting\ndata: hello\n\n\
+                    :keep-alive\n\n\
+                    data: two\ndata: lines\n\n");
+
+        let content_type = res.headers.content_type.unwrap();
+        assert_eq!(content_type.type_.as_slice(), "text");
+        assert_eq!(content_type.subtype.as_slice(), "event-stream");
+    }
+
+    #[test]
+    fn splits_data_on_every_line_end() {
+        let mut res = Response::new();
+        {
+            let mut events = EventStream::new(&mut res);
+            events.send_event(None, None, "a\r\nb\rid: forged\nc").unwrap();
+        }
+
+        let body = res.body.read_to_end().unwrap();
+        assert_eq!(from_utf8(body.as_slice()).unwrap(),
+                   "data: a\ndata: b\ndata: id: forged\ndata: c\n\n");
+    }
+
+    #[test]
+    fn refuses_line_ends_in_id_and_event() {
+        let mut res = Response::new();
+        {
+            let mut events = EventStream::new(&mut res);
+            let forged_id = events.send_event(Some("1\ndata: forged"), None, "x");
+            assert_eq!(forged_id.unwrap_err().kind, InvalidInput);
+            let forged_event = events.send_event(None, Some("tick\rdata: forged"), "x");
+            assert_eq!(forged_event.unwrap_err().kind, InvalidInput);
+        }
+
+        assert_eq!(res.body.read_to_end().unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,83 @@
+//! Exposes the `TeeBody` middleware, which copies request bodies
+//! to an audit sink.
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue};
+use super::logger::Logger;
+
+/// `Middleware` which sends a copy of every request body to a `Logger`,
+/// for instance for audit logging.
+///
+/// The body itself is left untouched, so `Middleware` further down the
+/// `Chain` can still read all of it. Only the first `cap` bytes of
+/// a body are copied to the sink.
+#[deriving(Clone)]
+pub struct TeeBody {
+    sink: Box<Logger + Send>,
+    cap: uint
+}
+
+impl TeeBody {
+    /// Create a new `TeeBody` copying up to `cap` bytes of each body to `sink`.
+    pub fn new(sink: Box<Logger + Send>, cap: uint) -> TeeBody {
+        TeeBody { sink: sink, cap: cap }
+    }
+}
+
+impl Middleware for TeeBody {
+    fn enter(&mut self, req: &mut Request, _: &mut Response) -> Status {
+        // Cut at the last character boundary within the cap.
+        let mut end = ::std::cmp::min(self.cap, req.body.len());
+        while !req.body.as_slice().is_char_boundary(end) { end -= 1; }
+
+        self.sink.log(req.body.as_slice().slice_to(end));
+        Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::method::Post;
+
+    use super::TeeBody;
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::middleware::{Status, Unwind, FromFn};
+
+    fn echo(req: &mut Request, res: &mut Response) -> Status {
+        res.serve(::http::status::Ok, req.body.as_slice());
+        Unwind
+    }
+
+    fn tee(body: &str, cap: uint) -> (Vec<String>, Vec<u8>) {
+        let logger = mock::CaptureLogger::new();
+        let mut chain: StackChain = Chain::new();
+        chain.link(TeeBody::new(logger.boxed(), cap));
+        chain.link(FromFn::new(echo));
+
+        let mut req = mock::request(Post, "http://localhost/");
+        req.body = body.to_string();
+        let mut res = mock::response();
+        let _ = chain.dispatch(&mut req, &mut res);
+
+        (logger.lines(), res.body.read_to_end().unwrap())
+    }
+
+    #[test]
+    fn copies_body_to_sink() {
+        let (audited, echoed) = tee("name=iron", 1024);
+        assert_eq!(audited, vec!["name=iron".to_string()]);
+        assert_eq!(echoed, b"name=iron".to_vec());
+    }
+
+    #[test]
+    fn caps_copied_body() {
+        let (audited, echoed) = tee("name=iron", 4);
+        assert_eq!(audited, vec!["name".to_string()]);
+        assert_eq!(echoed, b"name=iron".to_vec());
+    }
+}
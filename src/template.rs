@@ -0,0 +1,166 @@
+//! An integration point for rendering templates to a `Response`.
+
+use std::collections::HashMap;
+
+use http::headers::content_type::MediaType;
+use http::status::Ok;
+
+use super::response::Response;
+
+/// A template engine which `render` can use.
+///
+/// Implement this for a real engine to plug it in; `Substitution` is a
+/// simple default.
+pub trait TemplateEngine {
+    /// Render the template called `name` with `data`, or give a
+    /// description of why it could not be rendered.
+    fn render(&self, name: &str, data: &HashMap<String, String>) -> Result<String, String>;
+}
+
+/// A `TemplateEngine` which replaces every `{{key}}` in a template with
+/// the value of `key` in the data.
+///
+/// ```ignore
+/// let engine = Substitution::new().template("hello", "<h1>Hello, {{name}}!</h1>");
+/// ```
+///
+/// Values are HTML-escaped, so that data taken from a client can't inject
+/// markup. `{{{key}}}` inserts the value as it is, for data which already
+/// is HTML. A key missing from the data is an error.
+#[deriving(Clone)]
+pub struct Substitution {
+    templates: HashMap<String, String>
+}
+
+impl Substitution {
+    /// Create a new `Substitution` with no templates.
+    pub fn new() -> Substitution {
+        Substitution { templates: HashMap::new() }
+    }
+
+    /// Add the template `source` called `name`.
+    pub fn template(mut self, name: &str, source: &str) -> Substitution {
+        let _ = self.templates.insert(name.to_string(), source.to_string());
+        self
+    }
+}
+
+impl TemplateEngine for Substitution {
+    fn render(&self, name: &str, data: &HashMap<String, String>) -> Result<String, String> {
+        let mut rest = match self.templates.find_equiv(&name) {
+            Some(source) => source.as_slice(),
+            None => return Err(format!("No template called {}.", name))
+        };
+
+        let mut rendered = String::new();
+        loop {
+            let start = match rest.find_str("{{") {
+                Some(start) => start,
+                None => break
+            };
+            let (open, close) = if rest.slice_from(start).starts_with("{{{") {
+                ("{{{", "}}}")
+            } else {
+                ("{{", "}}")
+            };
+            let end = match rest.slice_from(start).find_str(close) {
+                Some(end) => start + end,
+                None => break
+            };
+
+            let key = rest.slice(start + open.len(), end).trim();
+            match data.find_equiv(&key) {
+                Some(value) => {
+                    rendered.push_str(rest.slice_to(start));
+                    if open == "{{{" {
+                        rendered.push_str(value.as_slice());
+                    } else {
+                        escape_html(value.as_slice(), &mut rendered);
+                    }
+                },
+                None => return Err(format!("No value for {} in template {}.", key, name))
+            }
+            rest = rest.slice_from(end + close.len());
+        }
+        rendered.push_str(rest);
+        Ok(rendered)
+    }
+}
+
+// Append `value` to `out` with the characters which are special in HTML,
+// in text or in a quoted attribute, replaced by entities.
+fn escape_html(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push_char(c)
+        }
+    }
+}
+
+/// Render the template called `name` with `data` using `engine`, and
+/// serve it as `text/html`.
+///
+/// The response is left untouched if the template could not be rendered.
+pub fn render<T: TemplateEngine>(res: &mut Response, engine: &T, name: &str,
+                                 data: &HashMap<String, String>) -> Result<(), String> {
+    let html = try!(engine.render(name, data));
+    res.headers.content_type = Some(MediaType {
+        type_: "text".to_string(),
+        subtype: "html".to_string(),
+        parameters: vec![("charset".to_string(), "utf-8".to_string())]
+    });
+    res.serve(Ok, html);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{render, Substitution};
+    use super::super::mock;
+
+    fn engine() -> Substitution {
+        Substitution::new().template("hello", "<h1>Hello, {{ name }}!</h1>")
+    }
+
+    #[test]
+    fn renders_substitution() {
+        let mut data = HashMap::new();
+        let _ = data.insert("name".to_string(), "Iron".to_string());
+
+        let mut res = mock::response();
+        render(&mut res, &engine(), "hello", &data).unwrap();
+
+        assert_eq!(res.status, Some(::http::status::Ok));
+        assert_eq!(res.headers.content_type.as_ref().unwrap().subtype.as_slice(), "html");
+        assert_eq!(res.body.read_to_end().unwrap(), b"<h1>Hello, Iron!</h1>".to_vec());
+    }
+
+    #[test]
+    fn escapes_values_unless_raw() {
+        let engine = Substitution::new().template("page", "<p>{{ name }}</p>{{{ footer }}}");
+        let mut data = HashMap::new();
+        let _ = data.insert("name".to_string(), "<script>\"x\" & 'y'</script>".to_string());
+        let _ = data.insert("footer".to_string(), "<hr>".to_string());
+
+        let mut res = mock::response();
+        render(&mut res, &engine, "page", &data).unwrap();
+
+        assert_eq!(res.body.read_to_end().unwrap(),
+                   b"<p>&lt;script&gt;&quot;x&quot; &amp; &#39;y&#39;&lt;/script&gt;</p><hr>".to_vec());
+    }
+
+    #[test]
+    fn fails_on_missing_value() {
+        let mut res = mock::response();
+        assert!(render(&mut res, &engine(), "hello", &HashMap::new()).is_err());
+        assert!(render(&mut res, &engine(), "goodbye", &HashMap::new()).is_err());
+        assert_eq!(res.status, None);
+    }
+}
@@ -0,0 +1,115 @@
+//! Exposes the `Timeout` middleware, which gives requests a deadline.
+
+use time::precise_time_ns;
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue};
+
+/// The time, in nanoseconds as given by `time::precise_time_ns`, by which
+/// the request should have been answered.
+///
+/// `Timeout` stores a `Deadline` in `Request::alloy`, so that slow handlers
+/// can check it and give up early.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Deadline(pub u64);
+
+impl Deadline {
+    /// Whether the deadline has passed.
+    pub fn expired(&self) -> bool {
+        let Deadline(at) = *self;
+        precise_time_ns() > at
+    }
+}
+
+/// `Middleware` which gives each request a `Deadline` a budget away.
+///
+/// A `Timeout` linked in the server's chain sets the default deadline,
+/// and one among the `Middleware` of a route overrides it for that route:
+///
+/// ```ignore
+/// server.chain.link(Timeout::new(1000));
+///
+/// router.route_with(Get, "/report", vec![box Timeout::new(30000) as Box<Middleware + Send>],
+///                   FromFn::new(report));
+/// ```
+///
+/// Handlers can't be interrupted, and a response they have finished may
+/// already have had effects, so the deadline is not enforced: it is up to
+/// handlers doing slow work to check the `Deadline` and give up early,
+/// answering as they see fit.
+#[deriving(Clone)]
+pub struct Timeout {
+    budget_ms: u64
+}
+
+impl Timeout {
+    /// Create a new `Timeout` giving requests `budget_ms` milliseconds.
+    pub fn new(budget_ms: u64) -> Timeout {
+        Timeout { budget_ms: budget_ms }
+    }
+}
+
+impl Middleware for Timeout {
+    fn enter(&mut self, req: &mut Request, _: &mut Response) -> Status {
+        req.alloy.insert(Deadline(precise_time_ns() + self.budget_ms * 1000000));
+        Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::precise_time_ns;
+    use http::method::Get;
+    use OkStatus = http::status::Ok;
+
+    use super::{Timeout, Deadline};
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::router::Router;
+    use super::super::middleware::{Middleware, Status, Unwind, FromFn};
+
+    // Serve the whole seconds left until the deadline.
+    fn seconds_left(req: &mut Request, res: &mut Response) -> Status {
+        let Deadline(at) = *req.alloy.find::<Deadline>().unwrap();
+        let now = precise_time_ns();
+        let left = if at > now { (at - now) / 1000000000 } else { 0 };
+        res.serve(OkStatus, format!("{}", left));
+        Unwind
+    }
+
+    fn chain(default_ms: u64) -> StackChain {
+        let mut router = Router::new();
+        router.route(Get, "/default", FromFn::new(seconds_left));
+        router.route_with(Get, "/report", vec![box Timeout::new(1000000) as Box<Middleware + Send>],
+                          FromFn::new(seconds_left));
+
+        let mut chain: StackChain = Chain::new();
+        chain.link(Timeout::new(default_ms));
+        chain.link(router);
+        chain
+    }
+
+    fn answer(default_ms: u64, path: &str) -> Response {
+        let mut req = mock::request(Get, format!("http://localhost{}", path).as_slice());
+        let mut res = mock::response();
+        let _ = chain(default_ms).dispatch(&mut req, &mut res);
+        res
+    }
+
+    #[test]
+    fn route_budget_overrides_default() {
+        assert_eq!(answer(10000, "/default").body.read_to_end().unwrap(), b"9".to_vec());
+        assert_eq!(answer(10000, "/report").body.read_to_end().unwrap(), b"999".to_vec());
+    }
+
+    #[test]
+    fn keeps_response_after_deadline() {
+        let mut res = answer(0, "/default");
+        assert_eq!(res.status, Some(OkStatus));
+        assert_eq!(res.body.read_to_end().unwrap(), b"0".to_vec());
+    }
+}
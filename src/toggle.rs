@@ -0,0 +1,117 @@
+//! Exposes the `Toggle` middleware, which switches other `Middleware`
+//! on and off at runtime.
+
+use std::fmt::Show;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, SeqCst};
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue};
+
+/// `Middleware` which wraps another, and passes requests through untouched
+/// while it is disabled.
+///
+/// The flag is shared by every clone of the `Toggle`, so flipping it
+/// affects all connections at once:
+///
+/// ```ignore
+/// let verbose = Toggle::new(VerboseLogger::new());
+/// let switch = verbose.switch();
+/// server.chain.link(verbose);
+///
+/// // Later, from anywhere:
+/// switch.store(false, SeqCst);
+/// ```
+///
+/// A `Toggle` which is disabled while a request is between `enter` and
+/// `exit` still calls `exit`, so the wrapped `Middleware` sees every
+/// request it entered out.
+#[deriving(Clone)]
+pub struct Toggle<M> {
+    inner: M,
+    enabled: Arc<AtomicBool>,
+    entered: bool
+}
+
+impl<M: Middleware> Toggle<M> {
+    /// Wrap `inner` in a `Toggle`, initially enabled.
+    pub fn new(inner: M) -> Toggle<M> {
+        Toggle::with_switch(inner, Arc::new(AtomicBool::new(true)))
+    }
+
+    /// Wrap `inner` in a `Toggle` controlled by `switch`, which may be
+    /// shared with other `Toggle`s.
+    pub fn with_switch(inner: M, switch: Arc<AtomicBool>) -> Toggle<M> {
+        Toggle { inner: inner, enabled: switch, entered: false }
+    }
+
+    /// The flag enabling the wrapped `Middleware`.
+    pub fn switch(&self) -> Arc<AtomicBool> {
+        self.enabled.clone()
+    }
+}
+
+impl<M: Middleware> Middleware for Toggle<M> {
+    fn enter(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        self.entered = self.enabled.load(SeqCst);
+        if self.entered { self.inner.enter(req, res) } else { Continue }
+    }
+
+    fn exit(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        if self.entered { self.inner.exit(req, res) } else { Continue }
+    }
+
+    fn on_error(&mut self, req: &mut Request, res: &mut Response, err: &mut Show) {
+        if self.entered { self.inner.on_error(req, res, err) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::SeqCst;
+    use http::method::Get;
+
+    use super::Toggle;
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::middleware::{Middleware, Status, Continue};
+
+    #[deriving(Clone)]
+    struct Counter { count: Arc<Mutex<uint>> }
+
+    impl Middleware for Counter {
+        fn enter(&mut self, _: &mut Request, _: &mut Response) -> Status {
+            *self.count.lock() += 1;
+            Continue
+        }
+    }
+
+    #[test]
+    fn skips_disabled_middleware() {
+        let count = Arc::new(Mutex::new(0u));
+        let toggle = Toggle::new(Counter { count: count.clone() });
+        let switch = toggle.switch();
+
+        let mut chain: StackChain = Chain::new();
+        chain.link(toggle);
+        let mut request = || {
+            let _ = chain.dispatch(&mut mock::request(Get, "http://localhost/"), &mut mock::response());
+        };
+
+        request();
+        assert_eq!(*count.lock(), 1);
+
+        switch.store(false, SeqCst);
+        request();
+        assert_eq!(*count.lock(), 1);
+
+        switch.store(true, SeqCst);
+        request();
+        assert_eq!(*count.lock(), 2);
+    }
+}
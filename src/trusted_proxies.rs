@@ -0,0 +1,209 @@
+//! Exposes the `TrustedProxies` middleware, which recovers the client's
+//! address and scheme from headers set by trusted reverse proxies.
+
+use std::ascii::StrAsciiExt;
+use std::io::net::ip::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr};
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue};
+
+/// `Middleware` which, for requests from a trusted proxy, replaces
+/// `Request::remote_addr` with the client address given by the
+/// `X-Forwarded-For` header, and the scheme of `Request::url` with the
+/// one given by `X-Forwarded-Proto`.
+///
+/// Proxies are trusted by CIDR range. Forwarded headers from any other
+/// peer are ignored, since clients can set them freely:
+///
+/// ```ignore
+/// server.chain.link(TrustedProxies::new().trust("10.0.0.0/8").trust("::1/128"));
+/// ```
+///
+/// `X-Forwarded-For` is read from the right, skipping trusted proxies,
+/// so the first untrusted address is taken as the client's. An entry
+/// which is not an address, such as `unknown` or one with a port, stops
+/// the search and the peer's address is kept, since the entries left of
+/// it can't be told apart from ones the client made up. The header
+/// carries no port, so the resulting `remote_addr` has port 0. `TrustedProxies`
+/// should be linked before any `Middleware` which uses the remote address
+/// or `Request::is_secure`.
+#[deriving(Clone)]
+pub struct TrustedProxies {
+    ranges: Vec<Cidr>
+}
+
+// A range of addresses, as the bytes of its first address and the
+// length of the prefix in bits.
+#[deriving(Clone)]
+struct Cidr {
+    bytes: Vec<u8>,
+    prefix: uint
+}
+
+impl Cidr {
+    fn parse(range: &str) -> Option<Cidr> {
+        let mut parts = range.splitn('/', 1);
+        let bytes = match parts.next().and_then(from_str::<IpAddr>) {
+            Some(ip) => ip_bytes(ip),
+            None => return None
+        };
+        let prefix = match parts.next() {
+            Some(prefix) => match from_str::<uint>(prefix) {
+                Some(prefix) if prefix <= bytes.len() * 8 => prefix,
+                _ => return None
+            },
+            None => bytes.len() * 8
+        };
+        Some(Cidr { bytes: bytes, prefix: prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let bytes = ip_bytes(ip);
+        if bytes.len() != self.bytes.len() { return false }
+
+        range(0, self.prefix).all(|bit| {
+            let mask = 0x80u8 >> (bit % 8);
+            bytes[bit / 8] & mask == self.bytes[bit / 8] & mask
+        })
+    }
+}
+
+fn ip_bytes(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        Ipv4Addr(a, b, c, d) => vec![a, b, c, d],
+        Ipv6Addr(a, b, c, d, e, f, g, h) => {
+            [a, b, c, d, e, f, g, h].iter()
+                .flat_map(|&part| vec![(part >> 8) as u8, part as u8].move_iter())
+                .collect()
+        }
+    }
+}
+
+impl TrustedProxies {
+    /// Create a new `TrustedProxies` trusting no proxies.
+    pub fn new() -> TrustedProxies {
+        TrustedProxies { ranges: vec![] }
+    }
+
+    /// Trust the proxies in `range`, a CIDR range such as `10.0.0.0/8`
+    /// or a single address.
+    ///
+    /// Fails if `range` is not a valid range.
+    pub fn trust(mut self, range: &str) -> TrustedProxies {
+        match Cidr::parse(range) {
+            Some(cidr) => self.ranges.push(cidr),
+            None => fail!("Invalid CIDR range: {}", range)
+        }
+        self
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.ranges.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+fn header(req: &Request, name: &str) -> Option<String> {
+    req.headers.extensions.iter()
+        .find(|&(key, _)| key.as_slice().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.clone())
+}
+
+impl Middleware for TrustedProxies {
+    fn enter(&mut self, req: &mut Request, _: &mut Response) -> Status {
+        match req.remote_addr {
+            Some(peer) if self.is_trusted(peer.ip) => (),
+            _ => return Continue
+        }
+
+        match header(req, "X-Forwarded-For") {
+            Some(forwarded) => {
+                let mut client = None;
+                for entry in forwarded.as_slice().split(',').rev() {
+                    match from_str::<IpAddr>(entry.trim()) {
+                        Some(ip) if self.is_trusted(ip) => (),
+                        Some(ip) => { client = Some(ip); break },
+                        None => break
+                    }
+                }
+                match client {
+                    Some(ip) => req.remote_addr = Some(SocketAddr { ip: ip, port: 0 }),
+                    None => ()
+                }
+            },
+            None => ()
+        }
+
+        match header(req, "X-Forwarded-Proto") {
+            Some(proto) => {
+                let proto = proto.as_slice().trim().to_ascii_lower();
+                if proto.as_slice() == "http" || proto.as_slice() == "https" {
+                    req.url.scheme = proto;
+                }
+            },
+            None => ()
+        }
+
+        Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::net::ip::{SocketAddr, IpAddr, Ipv4Addr};
+    use http::method::Get;
+
+    use super::TrustedProxies;
+    use super::super::mock;
+    use super::super::middleware::Middleware;
+    use super::super::request::Request;
+
+    fn proxied(peer: IpAddr) -> Request {
+        let mut req = mock::request(Get, "http://localhost/");
+        req.remote_addr = Some(SocketAddr { ip: peer, port: 4000 });
+        let _ = req.headers.extensions.insert("X-Forwarded-For".to_string(),
+                                              "203.0.113.7, 10.0.0.2".to_string());
+        let _ = req.headers.extensions.insert("X-Forwarded-Proto".to_string(), "https".to_string());
+        req
+    }
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::new().trust("10.0.0.0/8")
+    }
+
+    #[test]
+    fn trusts_forwarded_headers_from_trusted_peer() {
+        let mut req = proxied(Ipv4Addr(10, 0, 0, 1));
+        let _ = proxies().enter(&mut req, &mut mock::response());
+
+        assert_eq!(req.remote_addr, Some(SocketAddr { ip: Ipv4Addr(203, 0, 113, 7), port: 0 }));
+        assert!(req.is_secure());
+    }
+
+    #[test]
+    fn ignores_forwarded_headers_from_untrusted_peer() {
+        let mut req = proxied(Ipv4Addr(192, 168, 1, 1));
+        let _ = proxies().enter(&mut req, &mut mock::response());
+
+        assert_eq!(req.remote_addr, Some(SocketAddr { ip: Ipv4Addr(192, 168, 1, 1), port: 4000 }));
+        assert!(!req.is_secure());
+    }
+
+    #[test]
+    fn stops_at_unparseable_entry() {
+        for appended in ["unknown", "10.0.0.3:5678"].iter() {
+            let mut req = proxied(Ipv4Addr(10, 0, 0, 1));
+            let forwarded = format!("203.0.113.7, {}", appended);
+            let _ = req.headers.extensions.insert("X-Forwarded-For".to_string(), forwarded);
+            let _ = proxies().enter(&mut req, &mut mock::response());
+
+            assert_eq!(req.remote_addr, Some(SocketAddr { ip: Ipv4Addr(10, 0, 0, 1), port: 4000 }));
+        }
+    }
+
+    #[test]
+    #[should_fail]
+    fn rejects_invalid_range() {
+        let _ = TrustedProxies::new().trust("10.0.0.0/33");
+    }
+}
@@ -0,0 +1,102 @@
+//! Exposes the `Vary` middleware, which announces which request headers
+//! a response depends on.
+
+use std::ascii::StrAsciiExt;
+
+use super::request::Request;
+use super::response::Response;
+use super::middleware::{Middleware, Status, Continue};
+
+/// `Middleware` which merges the headers recorded with `Request::vary_on`
+/// into the `Vary` header of the response, so caches keep a copy of the
+/// response for every variant.
+///
+/// Negotiation helpers such as `Request::preferred_charset` record the
+/// headers they consult themselves. `Vary` sets the header in `exit`, so
+/// it should be linked first, to exit last:
+///
+/// ```ignore
+/// server.chain.link(Vary::new());
+/// ```
+#[deriving(Clone)]
+pub struct Vary;
+
+impl Vary {
+    /// Create a new `Vary`.
+    pub fn new() -> Vary { Vary }
+}
+
+impl Middleware for Vary {
+    fn exit(&mut self, req: &mut Request, res: &mut Response) -> Status {
+        let recorded = req.varies_on();
+        if recorded.is_empty() { return Continue }
+
+        let existing = res.headers.extensions.iter()
+            .find(|&(name, _)| name.as_slice().eq_ignore_ascii_case("Vary"))
+            .map(|(_, value)| value.clone());
+        let mut names: Vec<String> = match existing {
+            Some(value) => value.as_slice().split(',')
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect(),
+            None => vec![]
+        };
+
+        for name in recorded.move_iter() {
+            if !names.iter().any(|n| n.as_slice().eq_ignore_ascii_case(name.as_slice())) {
+                names.push(name);
+            }
+        }
+        res.set_header("Vary", names.connect(", ").as_slice());
+        Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::method::Get;
+
+    use super::Vary;
+    use super::super::mock;
+    use super::super::chain::Chain;
+    use super::super::chain::stackchain::StackChain;
+    use super::super::request::Request;
+    use super::super::response::Response;
+    use super::super::middleware::{Status, Unwind, FromFn};
+
+    // Serve JSON or HTML depending on the Accept header.
+    fn negotiate(req: &mut Request, res: &mut Response) -> Status {
+        req.vary_on("Accept");
+        let json = req.header_pairs().iter()
+            .any(|&(ref name, ref value)| name.as_slice() == "Accept" && value.as_slice().contains("json"));
+        res.serve(::http::status::Ok, if json { "{}" } else { "<p></p>" });
+        Unwind
+    }
+
+    fn charset(req: &mut Request, res: &mut Response) -> Status {
+        let _ = req.preferred_charset(["utf-8"]);
+        res.set_header("Vary", "Accept");
+        res.serve(::http::status::Ok, "");
+        Unwind
+    }
+
+    fn vary(handler: fn(&mut Request, &mut Response) -> Status) -> Option<String> {
+        let mut chain: StackChain = Chain::new();
+        chain.link(Vary::new());
+        chain.link(FromFn::new(handler));
+
+        let mut res = mock::response();
+        let _ = chain.dispatch(&mut mock::request(Get, "http://localhost/"), &mut res);
+        res.headers.extensions.find_equiv(&"Vary").map(|v| v.clone())
+    }
+
+    #[test]
+    fn varies_on_consulted_header() {
+        assert_eq!(vary(negotiate), Some("Accept".to_string()));
+    }
+
+    #[test]
+    fn merges_with_existing_vary() {
+        assert_eq!(vary(charset), Some("Accept, Accept-Charset".to_string()));
+    }
+}